@@ -1,10 +1,19 @@
-use crate::policy::EncryptionCookiePolicy;
+use std::borrow::Cow;
+
+use cookie::SameSite;
+
+use crate::{
+    config::CookieJarConfig, policy::EncryptionCookiePolicy, ConsentPolicy, CookieConsent,
+    PrefixEnforcement,
+};
 
 #[derive(Debug)]
 pub struct CookieJarBuilder {
     jar: cookie::CookieJar,
     key: cookie::Key,
+    previous_keys: Vec<cookie::Key>,
     encryption_policy: Option<EncryptionCookiePolicy>,
+    config: CookieJarConfig,
 }
 
 impl CookieJarBuilder {
@@ -12,21 +21,105 @@ impl CookieJarBuilder {
         Self {
             jar: cookie::CookieJar::new(),
             key,
+            previous_keys: Vec::new(),
             encryption_policy: None,
+            config: CookieJarConfig::default(),
         }
     }
 
+    /// Sets the keys that were used before the current one.
+    ///
+    /// Cookies signed or encrypted with a previous key are still accepted when read, while new
+    /// cookies are always written with the primary key. Keys are tried in the given order.
+    pub fn with_previous_keys<I>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = cookie::Key>,
+    {
+        self.previous_keys = keys.into_iter().collect();
+        self
+    }
+
     pub fn with_encryption_policy(mut self, policy: EncryptionCookiePolicy) -> Self {
         self.encryption_policy = Some(policy);
         self
     }
 
+    /// Sets the maximum size in bytes of a value encoded by
+    /// [`CookieJar::insert_json`](crate::CookieJar::insert_json) once signed or encrypted, 4000
+    /// bytes by default.
+    pub fn with_max_json_size(mut self, size: usize) -> Self {
+        self.config.max_json_size = size;
+        self
+    }
+
+    /// Sets the maximum size in bytes of the name and value of a cookie once signed or
+    /// encrypted, 4096 bytes by default.
+    pub fn with_max_cookie_size(mut self, size: usize) -> Self {
+        self.config.max_cookie_size = size;
+        self
+    }
+
+    /// Sets the maximum number of cookies a jar holds, 50 by default.
+    pub fn with_max_cookie_count(mut self, count: usize) -> Self {
+        self.config.max_cookie_count = count;
+        self
+    }
+
+    /// Makes the `CookieLayer` log a warning when the `Set-Cookie` headers of a response add up to
+    /// more than `size` bytes. Requires the `tracing` feature.
+    pub fn with_max_set_cookie_size(mut self, size: usize) -> Self {
+        self.config.max_set_cookie_size = Some(size);
+        self
+    }
+
+    /// Sets the path applied to every inserted cookie that does not set one.
+    pub fn with_default_path<T: Into<Cow<'static, str>>>(mut self, path: T) -> Self {
+        self.config.path = Some(path.into());
+        self
+    }
+
+    /// Sets the domain applied to every inserted cookie that does not set one.
+    pub fn with_default_domain<T: Into<Cow<'static, str>>>(mut self, domain: T) -> Self {
+        self.config.domain = Some(domain.into());
+        self
+    }
+
+    /// Sets the `Secure` flag applied to every inserted cookie that does not set one.
+    pub fn with_default_secure(mut self, secure: bool) -> Self {
+        self.config.secure = Some(secure);
+        self
+    }
+
+    /// Sets the `SameSite` attribute applied to every inserted cookie that does not set one.
+    pub fn with_default_same_site(mut self, same_site: SameSite) -> Self {
+        self.config.same_site = Some(same_site);
+        self
+    }
+
+    /// Categorizes cookies and only sets those the user consented to, see
+    /// [`CookieJar::insert`](crate::CookieJar::insert).
+    pub fn with_consent_policy(mut self, policy: ConsentPolicy) -> Self {
+        self.config.consent = Some(policy);
+        self
+    }
+
+    /// Sets what happens to `__Host-` and `__Secure-` cookies that violate the requirements of
+    /// their prefix, they are normalized by default.
+    pub fn with_prefix_enforcement(mut self, enforcement: PrefixEnforcement) -> Self {
+        self.config.prefix_enforcement = enforcement;
+        self
+    }
+
     pub fn build(self) -> crate::CookieJar {
         crate::CookieJar {
             jar: self.jar,
             // Unwrapping is safe because we know that the key is always present
             key: self.key.into(),
+            previous_keys: self.previous_keys.into(),
             encryption_policy: self.encryption_policy.unwrap_or_default().into(),
+            config: self.config.into(),
+            consent: CookieConsent::default(),
+            layered: false,
         }
     }
 }
@@ -0,0 +1,60 @@
+use std::borrow::Cow;
+
+use cookie::{Cookie, SameSite};
+
+use crate::{ConsentPolicy, PrefixEnforcement};
+
+/// Settings applied by a `CookieJar` when inserting cookies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CookieJarConfig {
+    /// The maximum size in bytes of an encoded JSON value, once signed or encrypted.
+    pub(crate) max_json_size: usize,
+    /// The maximum size in bytes of the name and encoded value of a cookie.
+    pub(crate) max_cookie_size: usize,
+    /// The maximum number of cookies in a jar.
+    pub(crate) max_cookie_count: usize,
+    /// The total size in bytes of the `Set-Cookie` headers of a response above which the
+    /// `CookieLayer` warns.
+    pub(crate) max_set_cookie_size: Option<usize>,
+    pub(crate) path: Option<Cow<'static, str>>,
+    pub(crate) domain: Option<Cow<'static, str>>,
+    pub(crate) secure: Option<bool>,
+    pub(crate) same_site: Option<SameSite>,
+    pub(crate) consent: Option<ConsentPolicy>,
+    pub(crate) prefix_enforcement: PrefixEnforcement,
+}
+
+impl CookieJarConfig {
+    /// Fills in the attributes the cookie does not set itself.
+    pub(crate) fn apply_defaults(&self, cookie: &mut Cookie<'static>) {
+        if let (None, Some(path)) = (cookie.path(), &self.path) {
+            cookie.set_path(path.clone());
+        }
+        if let (None, Some(domain)) = (cookie.domain(), &self.domain) {
+            cookie.set_domain(domain.clone());
+        }
+        if let (None, Some(secure)) = (cookie.secure(), self.secure) {
+            cookie.set_secure(secure);
+        }
+        if let (None, Some(same_site)) = (cookie.same_site(), self.same_site) {
+            cookie.set_same_site(same_site);
+        }
+    }
+}
+
+impl Default for CookieJarConfig {
+    fn default() -> Self {
+        Self {
+            max_json_size: 4000,
+            max_cookie_size: 4096,
+            max_cookie_count: 50,
+            max_set_cookie_size: None,
+            path: None,
+            domain: None,
+            secure: None,
+            same_site: None,
+            consent: None,
+            prefix_enforcement: PrefixEnforcement::default(),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::{fmt, str::FromStr};
+
+use crate::{CookieKey, CookieMap};
+
+/// The purpose of a cookie, used to honour the consent given by the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CookieCategory {
+    /// Strictly necessary cookies, always allowed.
+    Essential,
+    Preferences,
+    Analytics,
+    Marketing,
+}
+
+impl CookieCategory {
+    const ALL: [Self; 4] = [
+        Self::Essential,
+        Self::Preferences,
+        Self::Analytics,
+        Self::Marketing,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Essential => "essential",
+            Self::Preferences => "preferences",
+            Self::Analytics => "analytics",
+            Self::Marketing => "marketing",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl fmt::Display for CookieCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CookieCategory {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|category| category.as_str() == value)
+            .ok_or(())
+    }
+}
+
+/// The categories of cookies the user agreed to.
+///
+/// Essential cookies are always allowed. The consent is stored in the consent cookie as a comma
+/// separated list of categories, such as `preferences,analytics`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CookieConsent {
+    granted: u8,
+}
+
+impl CookieConsent {
+    /// Consent to essential cookies only, the default when the user has not chosen yet.
+    pub fn essential() -> Self {
+        Self {
+            granted: CookieCategory::Essential.bit(),
+        }
+    }
+
+    /// Consent to every category.
+    pub fn all() -> Self {
+        CookieCategory::ALL
+            .into_iter()
+            .fold(Self::essential(), Self::with)
+    }
+
+    /// Adds `category` to the consent.
+    #[must_use]
+    pub fn with(self, category: CookieCategory) -> Self {
+        Self {
+            granted: self.granted | category.bit(),
+        }
+    }
+
+    /// Returns `true` if cookies of `category` may be set.
+    pub fn allows(&self, category: CookieCategory) -> bool {
+        self.granted & category.bit() != 0
+    }
+
+    /// Parses the value of the consent cookie, ignoring unknown categories.
+    pub fn parse(value: &str) -> Self {
+        if value.trim() == "all" {
+            return Self::all();
+        }
+        value
+            .split(',')
+            .filter_map(|category| category.trim().parse().ok())
+            .fold(Self::essential(), Self::with)
+    }
+}
+
+impl Default for CookieConsent {
+    fn default() -> Self {
+        Self::essential()
+    }
+}
+
+impl fmt::Display for CookieConsent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let categories: Vec<_> = CookieCategory::ALL
+            .into_iter()
+            .filter(|category| *category != CookieCategory::Essential && self.allows(*category))
+            .map(|category| category.as_str())
+            .collect();
+        f.write_str(&categories.join(","))
+    }
+}
+
+/// Categorizes cookies and names the cookie holding the consent of the user.
+///
+/// Cookies matching no rule fall back to the default category, [`CookieCategory::Essential`]
+/// unless changed with [`with_default_category`](Self::with_default_category). The consent
+/// cookie itself is always essential.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsentPolicy {
+    categories: CookieMap<CookieCategory>,
+    default_category: CookieCategory,
+    cookie_name: CookieKey,
+}
+
+impl ConsentPolicy {
+    /// Creates a policy reading the consent from the cookie named `cookie_name`.
+    pub fn new<T: Into<CookieKey>>(cookie_name: T) -> Self {
+        Self {
+            categories: CookieMap::default(),
+            default_category: CookieCategory::Essential,
+            cookie_name: cookie_name.into(),
+        }
+    }
+
+    /// Sets the category of the cookies matching no rule.
+    #[must_use]
+    pub fn with_default_category(mut self, category: CookieCategory) -> Self {
+        self.default_category = category;
+        self
+    }
+
+    pub fn insert<T: Into<CookieKey>>(&mut self, key: T, category: CookieCategory) {
+        self.categories.insert(key, category);
+    }
+
+    /// Applies `category` to every cookie whose name starts with `prefix`.
+    pub fn insert_prefix<T: Into<CookieKey>>(&mut self, prefix: T, category: CookieCategory) {
+        self.categories.insert_prefix(prefix, category);
+    }
+
+    /// Applies `category` to every cookie whose name matches the glob `pattern`.
+    pub fn insert_pattern<T: Into<CookieKey>>(&mut self, pattern: T, category: CookieCategory) {
+        self.categories.insert_pattern(pattern, category);
+    }
+
+    /// Applies `category` to every cookie whose name satisfies `predicate`.
+    pub fn insert_predicate<F>(&mut self, predicate: F, category: CookieCategory)
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.categories.insert_predicate(predicate, category);
+    }
+
+    /// Returns the name of the cookie holding the consent of the user.
+    pub fn cookie_name(&self) -> &str {
+        &self.cookie_name
+    }
+
+    /// Returns the category of the cookie named `name`.
+    pub fn category<T: AsRef<str>>(&self, name: T) -> CookieCategory {
+        let name = name.as_ref();
+        if name == self.cookie_name {
+            return CookieCategory::Essential;
+        }
+        self.categories
+            .resolve(name)
+            .unwrap_or(self.default_category)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consent_round_trip() {
+        let consent = CookieConsent::parse("analytics, unknown,preferences");
+
+        assert!(consent.allows(CookieCategory::Essential));
+        assert!(consent.allows(CookieCategory::Analytics));
+        assert!(consent.allows(CookieCategory::Preferences));
+        assert!(!consent.allows(CookieCategory::Marketing));
+        assert_eq!(consent.to_string(), "preferences,analytics");
+        assert_eq!(CookieConsent::parse(&consent.to_string()), consent);
+        assert_eq!(CookieConsent::parse("all"), CookieConsent::all());
+        assert_eq!(CookieConsent::parse(""), CookieConsent::essential());
+    }
+
+    #[test]
+    fn test_policy_categories() {
+        let mut policy =
+            ConsentPolicy::new("consent").with_default_category(CookieCategory::Marketing);
+        policy.insert("theme", CookieCategory::Preferences);
+        policy.insert_prefix("_ga", CookieCategory::Analytics);
+
+        assert_eq!(policy.category("theme"), CookieCategory::Preferences);
+        assert_eq!(policy.category("_ga_123"), CookieCategory::Analytics);
+        assert_eq!(policy.category("tracker"), CookieCategory::Marketing);
+        assert_eq!(policy.category("consent"), CookieCategory::Essential);
+    }
+}
@@ -0,0 +1,69 @@
+use std::borrow::Cow;
+
+use axum_core::response::{IntoResponse, Response};
+use http::StatusCode;
+
+use crate::{CookieCategory, CookiePrefix, PrefixViolation};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CookieError {
+    #[error("failed to serialize the value of cookie {name:?}")]
+    Serialize {
+        name: Cow<'static, str>,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("failed to deserialize the value of cookie {name:?}")]
+    Deserialize {
+        name: Cow<'static, str>,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("the value of cookie {name:?} is not valid base64url")]
+    Decode {
+        name: Cow<'static, str>,
+        #[source]
+        source: base64::DecodeError,
+    },
+
+    #[error("cookie {name:?} is in the {category} category which the user did not consent to")]
+    NotConsented {
+        name: Cow<'static, str>,
+        category: CookieCategory,
+    },
+
+    #[error("cookie {name:?} does not meet the requirements of the {prefix} prefix: {violation}")]
+    InvalidPrefix {
+        name: Cow<'static, str>,
+        prefix: CookiePrefix,
+        violation: PrefixViolation,
+    },
+
+    #[error("cookie {name:?} is {size} bytes which exceeds the limit of {limit} bytes")]
+    TooLarge {
+        name: Cow<'static, str>,
+        size: usize,
+        limit: usize,
+    },
+
+    #[error(
+        "cookie {name:?} cannot be added, the jar already holds {count} cookies out of {limit}"
+    )]
+    TooMany {
+        name: Cow<'static, str>,
+        count: usize,
+        limit: usize,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Cookie jar extension is missing")]
+pub struct CookieJarMissingFromExt;
+
+impl IntoResponse for CookieJarMissingFromExt {
+    fn into_response(self) -> Response {
+        (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+    }
+}
@@ -0,0 +1,185 @@
+use std::borrow::Cow;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{error::CookieError, Cookie, CookieJar, TypedCookie};
+
+impl CookieJar {
+    /// Returns the cookie named `name` along with its kind, verified or decrypted according to
+    /// the policy.
+    pub fn get_typed<T: Into<Cow<'static, str>>>(&self, name: T) -> Option<TypedCookie<'static>> {
+        let name = name.into();
+        let kind = self.encryption_policy.cookie_kind(&name);
+        self.get(name).map(|cookie| TypedCookie { cookie, kind })
+    }
+
+    /// Returns the value of the cookie named `name` deserialized from JSON.
+    ///
+    /// Returns `Ok(None)` if the cookie is missing or cannot be verified.
+    ///
+    /// # Errors
+    /// Returns a `CookieError` if the value is not base64url encoded JSON of type `T`.
+    pub fn get_json<T, N>(&self, name: N) -> Result<Option<T>, CookieError>
+    where
+        T: DeserializeOwned,
+        N: Into<Cow<'static, str>>,
+    {
+        self.get_typed(name).map(|cookie| cookie.json()).transpose()
+    }
+
+    /// Inserts a cookie named `name` holding `value` serialized as base64url encoded JSON.
+    ///
+    /// # Errors
+    /// Returns a `CookieError` if the value cannot be serialized, if the encoded value exceeds the
+    /// maximum JSON size of the jar once signed or encrypted, or if the cookie cannot be inserted,
+    /// see [`try_insert`](Self::try_insert).
+    pub fn insert_json<T, N>(self, name: N, value: &T) -> Result<Self, CookieError>
+    where
+        T: Serialize + ?Sized,
+        N: Into<Cow<'static, str>>,
+    {
+        let name = name.into();
+        let json = serde_json::to_vec(value).map_err(|source| CookieError::Serialize {
+            name: name.clone(),
+            source,
+        })?;
+        let cookie = Cookie::new(name.clone(), URL_SAFE_NO_PAD.encode(json));
+
+        let size = self.encode(cookie.clone()).value().len();
+        let limit = self.config.max_json_size;
+        if size > limit {
+            return Err(CookieError::TooLarge { name, size, limit });
+        }
+
+        self.try_insert(cookie)
+    }
+}
+
+impl TypedCookie<'_> {
+    /// Deserializes the base64url encoded JSON value of the cookie.
+    ///
+    /// # Errors
+    /// Returns a `CookieError` if the value is not base64url encoded JSON of type `T`.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, CookieError> {
+        let name = || Cow::Owned(self.cookie.name().to_owned());
+        let json = URL_SAFE_NO_PAD
+            .decode(self.cookie.value())
+            .map_err(|source| CookieError::Decode {
+                name: name(),
+                source,
+            })?;
+        serde_json::from_slice(&json).map_err(|source| CookieError::Deserialize {
+            name: name(),
+            source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header, HeaderMap};
+    use serde::Deserialize;
+
+    use crate::{CookieKind, EncryptionCookiePolicy};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Preferences {
+        theme: String,
+        font_size: u8,
+    }
+
+    fn preferences() -> Preferences {
+        Preferences {
+            theme: "dark".into(),
+            font_size: 14,
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut policy = EncryptionCookiePolicy::default();
+        policy.insert("prefs", CookieKind::Private);
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .with_encryption_policy(policy)
+            .build();
+
+        let jar = jar.insert_json("prefs", &preferences()).unwrap();
+
+        let value: Preferences = jar.get_json("prefs").unwrap().unwrap();
+        assert_eq!(value, preferences());
+        assert_eq!(jar.get_typed("prefs").unwrap().kind(), CookieKind::Private);
+        assert!(jar.get_json::<Preferences, _>("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_json_from_request() {
+        let value = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&preferences()).unwrap());
+        let truncated = URL_SAFE_NO_PAD.encode(br#"{"theme":"dark""#);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            format!("prefs={value}; broken=not!base64; truncated={truncated}")
+                .parse()
+                .unwrap(),
+        );
+
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .build()
+            .from_headers(&headers);
+
+        let prefs: Preferences = jar.get_json("prefs").unwrap().unwrap();
+        assert_eq!(prefs, preferences());
+        assert!(matches!(
+            jar.get_json::<Preferences, _>("broken"),
+            Err(CookieError::Decode { name, .. }) if name == "broken"
+        ));
+        assert!(matches!(
+            jar.get_json::<Preferences, _>("truncated"),
+            Err(CookieError::Deserialize { name, .. }) if name == "truncated"
+        ));
+    }
+
+    #[test]
+    fn test_json_too_large() {
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .with_max_json_size(16)
+            .build();
+
+        let result = jar.insert_json("prefs", &preferences());
+        assert!(matches!(
+            result,
+            Err(CookieError::TooLarge { limit: 16, .. })
+        ));
+
+        // The encrypted value is measured, not the JSON.
+        let mut policy = EncryptionCookiePolicy::default();
+        policy.insert("prefs", CookieKind::Private);
+        let builder = || {
+            CookieJar::builder(cookie::Key::generate())
+                .with_encryption_policy(policy.clone())
+                .with_max_json_size(60)
+        };
+        let plain = CookieJar::builder(cookie::Key::generate())
+            .with_max_json_size(60)
+            .build();
+        assert!(plain.insert_json("prefs", &preferences()).is_ok());
+        assert!(matches!(
+            builder().build().insert_json("prefs", &preferences()),
+            Err(CookieError::TooLarge { limit: 60, .. })
+        ));
+
+        // A cookie the jar refuses is an error rather than silently dropped.
+        let result = builder()
+            .with_max_json_size(4000)
+            .with_max_cookie_size(64)
+            .build()
+            .insert_json("prefs", &preferences());
+        assert!(matches!(
+            result,
+            Err(CookieError::TooLarge { limit: 64, .. })
+        ));
+    }
+}
@@ -3,17 +3,29 @@ use std::sync::Arc;
 
 pub use cookie::time::Duration;
 pub use cookie::Cookie;
+pub use cookie::SameSite;
 use http::{header, HeaderMap};
 
 mod builder;
+mod config;
+mod consent;
+mod error;
+mod json;
 mod kind;
 mod map;
+mod pattern;
 mod policy;
+mod prefix;
 
+use config::CookieJarConfig;
+pub use consent::{ConsentPolicy, CookieCategory, CookieConsent};
+pub use error::{CookieError, CookieJarMissingFromExt};
 pub use kind::CookieKind;
 pub use map::CookieKey;
 pub use map::CookieMap;
+pub use pattern::{CookiePattern, CookiePredicate};
 pub use policy::EncryptionCookiePolicy;
+pub use prefix::{CookiePrefix, PrefixEnforcement, PrefixViolation};
 
 pub mod middleware;
 
@@ -21,7 +33,13 @@ pub mod middleware;
 pub struct CookieJar {
     jar: cookie::CookieJar,
     key: Arc<cookie::Key>,
+    previous_keys: Arc<[cookie::Key]>,
     encryption_policy: Arc<EncryptionCookiePolicy>,
+    config: Arc<CookieJarConfig>,
+    /// The consent read from the request.
+    consent: CookieConsent,
+    /// Whether the jar was handed out by the `CookieLayer`, which emits its queued cookies.
+    layered: bool,
 }
 
 impl CookieJar {
@@ -29,43 +47,288 @@ impl CookieJar {
         builder::CookieJarBuilder::new(key)
     }
 
+    /// Adds `cookie` to the jar, signing or encrypting it according to the policy.
+    ///
+    /// The jar defaults for path, domain, `Secure` and `SameSite` are applied to any attribute
+    /// the cookie does not set itself. When a [`ConsentPolicy`] is configured, cookies in a
+    /// category the user did not consent to are silently dropped. `__Host-` and `__Secure-`
+    /// cookies are adjusted to meet the requirements of their prefix, or dropped when the jar
+    /// uses [`PrefixEnforcement::Reject`]. Cookies exceeding the size or count limits of the jar
+    /// once signed or encrypted are dropped with a warning, as browsers would ignore them. Use
+    /// [`try_insert`](Self::try_insert) to get an error instead of dropping the cookie.
     #[must_use]
     pub fn insert(mut self, cookie: Cookie<'static>) -> Self {
+        if let Err(err) = self.push(cookie, false) {
+            #[cfg(feature = "tracing")]
+            match err {
+                CookieError::NotConsented { .. } => {
+                    tracing::debug!(error = %err, "Dropping cookie")
+                }
+                _ => tracing::warn!(error = %err, "Dropping cookie"),
+            }
+            #[cfg(not(feature = "tracing"))]
+            drop(err);
+        }
+        self
+    }
+
+    /// Adds `cookie` to the jar, failing if it would be dropped by [`insert`](Self::insert).
+    ///
+    /// # Errors
+    /// Returns `CookieError::NotConsented` if the category of the cookie is not consented to,
+    /// `CookieError::InvalidPrefix` if the jar rejects prefix violations and the cookie has one,
+    /// `CookieError::TooLarge` if the encoded cookie exceeds the maximum cookie size and
+    /// `CookieError::TooMany` if the jar already holds the maximum number of cookies.
+    pub fn try_insert(mut self, cookie: Cookie<'static>) -> Result<Self, CookieError> {
+        self.push(cookie, false)?;
+        Ok(self)
+    }
+
+    /// Adds a strictly necessary `cookie` to the jar, regardless of the consent of the user.
+    ///
+    /// Use it for cookies the application cannot work without, such as the session cookie,
+    /// whatever category the [`ConsentPolicy`] assigns them.
+    ///
+    /// # Errors
+    /// Fails like [`try_insert`](Self::try_insert), except for `CookieError::NotConsented`.
+    pub fn try_insert_essential(mut self, cookie: Cookie<'static>) -> Result<Self, CookieError> {
+        self.push(cookie, true)?;
+        Ok(self)
+    }
+
+    fn push(&mut self, mut cookie: Cookie<'static>, essential: bool) -> Result<(), CookieError> {
+        if !essential {
+            if let Some(category) = self.category_denied(cookie.name()) {
+                return Err(CookieError::NotConsented {
+                    name: Cow::Owned(cookie.name().to_owned()),
+                    category,
+                });
+            }
+        }
+        self.prepare(&mut cookie)?;
+        let cookie = self.encode(cookie);
+
+        let size = cookie.encoded().stripped().to_string().len();
+        let limit = self.config.max_cookie_size;
+        if size > limit {
+            return Err(CookieError::TooLarge {
+                name: Cow::Owned(cookie.name().to_owned()),
+                size,
+                limit,
+            });
+        }
+
+        let limit = self.config.max_cookie_count;
+        let count = self.jar.iter().count();
+        if self.jar.get(cookie.name()).is_none() && count >= limit {
+            return Err(CookieError::TooMany {
+                name: Cow::Owned(cookie.name().to_owned()),
+                count,
+                limit,
+            });
+        }
+
+        self.jar.add(cookie);
+        Ok(())
+    }
+
+    /// Applies the jar defaults and checks `cookie` against the prefix rules.
+    fn prepare(&self, cookie: &mut Cookie<'static>) -> Result<(), CookieError> {
+        self.config.apply_defaults(cookie);
+
+        let Some(prefix) = CookiePrefix::from_name(cookie.name()) else {
+            return Ok(());
+        };
+        match self.config.prefix_enforcement {
+            PrefixEnforcement::Normalize => prefix.normalize(cookie),
+            PrefixEnforcement::Reject => {
+                prefix
+                    .validate(cookie)
+                    .map_err(|violation| CookieError::InvalidPrefix {
+                        name: Cow::Owned(cookie.name().to_owned()),
+                        prefix,
+                        violation,
+                    })?
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `cookie` signed or encrypted according to the policy, as it will be sent.
+    fn encode(&self, cookie: Cookie<'static>) -> Cookie<'static> {
         let name = cookie.name().to_owned();
-        let kind = self.encryption_policy.cookie_kind(name.clone());
-        match kind {
-            CookieKind::Normal => self.jar.add(cookie),
-            CookieKind::Signed => self.jar.signed_mut(&self.key).add(cookie),
-            CookieKind::Private => self.jar.private_mut(&self.key).add(cookie),
+        let mut jar = cookie::CookieJar::new();
+        match self.encryption_policy.cookie_kind(&name) {
+            CookieKind::Normal => return cookie,
+            CookieKind::Signed => jar.signed_mut(&self.key).add(cookie),
+            CookieKind::Private => jar.private_mut(&self.key).add(cookie),
         };
-        self
+        jar.get(&name).cloned().expect("the cookie was just added")
     }
 
-    pub fn get<T: Into<Cow<'static, str>>>(&self, name: T) -> Option<Cookie<'static>> {
-        let name_ref: Cow<'_, str> = name.into();
-        match self.encryption_policy.cookie_kind(name_ref.clone()) {
-            CookieKind::Normal => self.jar.get(name_ref.as_ref()).cloned(),
-            CookieKind::Signed => {
-                let signed_jar = self.jar.signed(&self.key);
-                signed_jar
-                    .get(name_ref.as_ref())
-                    .and_then(|cookie| signed_jar.verify(cookie))
+    /// Returns the consent read from the request, or granted with
+    /// [`grant_consent`](Self::grant_consent).
+    pub fn consent(&self) -> CookieConsent {
+        self.consent
+    }
+
+    /// Returns `true` if the cookie named `name` may be set.
+    pub fn allows<T: AsRef<str>>(&self, name: T) -> bool {
+        self.category_denied(name).is_none()
+    }
+
+    /// Records `consent` and queues the consent cookie holding it.
+    ///
+    /// Does nothing when no [`ConsentPolicy`] is configured.
+    #[must_use]
+    pub fn grant_consent(mut self, consent: CookieConsent) -> Self {
+        let Some(policy) = &self.config.consent else {
+            return self;
+        };
+        let cookie = Cookie::new(policy.cookie_name().to_owned(), consent.to_string());
+        self.consent = consent;
+        self.forever(cookie)
+    }
+
+    /// Removes every cookie of the request the user did not consent to.
+    #[must_use]
+    pub fn expire_non_consented(self) -> Self {
+        let denied: Vec<_> = self
+            .jar
+            .iter()
+            .filter(|cookie| self.category_denied(cookie.name()).is_some())
+            .map(|cookie| cookie.name().to_owned())
+            .collect();
+        denied.into_iter().fold(self, |jar, name| jar.remove(name))
+    }
+
+    /// Returns the category of the cookie named `name` if the user did not consent to it.
+    fn category_denied<T: AsRef<str>>(&self, name: T) -> Option<CookieCategory> {
+        let category = self.config.consent.as_ref()?.category(name);
+        (!self.consent.allows(category)).then_some(category)
+    }
+
+    /// Adds `cookie` to the jar with an expiry twenty years in the future.
+    #[must_use]
+    pub fn forever(self, mut cookie: Cookie<'static>) -> Self {
+        cookie.make_permanent();
+        self.insert(cookie)
+    }
+
+    /// Removes the cookie named `name` and emits a `Set-Cookie` header expiring it on the client.
+    ///
+    /// The removal cookie carries the path and domain of the cookie being removed when it was
+    /// inserted during this request, and the jar defaults otherwise, as browsers only delete a
+    /// cookie whose path and domain match. Removal works the same for signed and private cookies.
+    #[must_use]
+    pub fn remove<T: Into<Cow<'static, str>>>(mut self, name: T) -> Self {
+        let name = name.into();
+        let mut removal = Cookie::new(name.clone(), "");
+        if let Some(cookie) = self.jar.get(&name) {
+            if let Some(path) = cookie.path() {
+                removal.set_path(path.to_owned());
             }
-            CookieKind::Private => {
-                let private_jar = self.jar.private(&self.key);
-                private_jar
-                    .get(name_ref.as_ref())
-                    .and_then(|cookie| private_jar.decrypt(cookie))
+            if let Some(domain) = cookie.domain() {
+                removal.set_domain(domain.to_owned());
             }
         }
+        self.config.apply_defaults(&mut removal);
+        if let Some(prefix) = CookiePrefix::from_name(&name) {
+            prefix.normalize(&mut removal);
+        }
+
+        // The jar only emits a removal for cookies it has seen in the request, so make sure it
+        // knows about this one before removing it.
+        self.jar.add_original(Cookie::new(name, ""));
+        self.jar.remove(removal);
+        self
+    }
+
+    /// Removes the cookie named `name`, an alias of [`remove`](Self::remove).
+    #[must_use]
+    pub fn forget<T: Into<Cow<'static, str>>>(self, name: T) -> Self {
+        self.remove(name)
+    }
+
+    /// Returns the cookie named `name`, verified or decrypted according to the policy.
+    ///
+    /// Signed and private cookies are checked against the primary key first and then against
+    /// each previous key, so cookies issued before a key rotation remain readable.
+    pub fn get<T: Into<Cow<'static, str>>>(&self, name: T) -> Option<Cookie<'static>> {
+        self.get_with_key(name).map(|(cookie, _)| cookie)
+    }
+
+    /// Returns `true` if the cookie named `name` is only valid under a previous key.
+    ///
+    /// Such a cookie should be re-issued so it gets signed or encrypted with the primary key,
+    /// see [`reissue_stale`](Self::reissue_stale).
+    pub fn needs_reissue<T: Into<Cow<'static, str>>>(&self, name: T) -> bool {
+        self.get_with_key(name).is_some_and(|(_, stale)| stale)
+    }
+
+    /// Returns every cookie that is only valid under a previous key, in plain text.
+    pub fn stale(&self) -> Vec<Cookie<'static>> {
+        self.jar
+            .iter()
+            .filter_map(|cookie| self.get_with_key(cookie.name().to_owned()))
+            .filter_map(|(cookie, stale)| stale.then_some(cookie))
+            .collect()
+    }
+
+    /// Re-inserts every stale cookie so it is signed or encrypted with the primary key.
+    #[must_use]
+    pub fn reissue_stale(self) -> Self {
+        let stale = self.stale();
+        stale
+            .into_iter()
+            .fold(self, |jar, cookie| jar.insert(cookie))
+    }
+
+    /// Returns the decoded cookie and whether it was decoded with a previous key.
+    fn get_with_key<T: Into<Cow<'static, str>>>(&self, name: T) -> Option<(Cookie<'static>, bool)> {
+        let name: Cow<'_, str> = name.into();
+        let cookie = self.jar.get(name.as_ref())?.clone();
+        let kind = self.encryption_policy.cookie_kind(name);
+        if kind == CookieKind::Normal {
+            return Some((cookie, false));
+        }
+
+        std::iter::once(self.key.as_ref())
+            .chain(self.previous_keys.iter())
+            .enumerate()
+            .find_map(|(index, key)| {
+                let cookie = match kind {
+                    CookieKind::Signed => self.jar.signed(key).verify(cookie.clone()),
+                    CookieKind::Private => self.jar.private(key).decrypt(cookie.clone()),
+                    CookieKind::Normal => Some(cookie.clone()),
+                };
+                cookie.map(|cookie| (cookie, index > 0))
+            })
     }
 
-    pub fn extend(self, cookies: Self) -> Self {
-        let mut jar = self;
-        for cookie in cookies.jar.iter() {
-            jar = jar.insert(cookie.clone());
+    /// Merges the cookies queued in `cookies` into this jar.
+    ///
+    /// Queued cookies are copied exactly as they will be sent, so signed and private cookies are
+    /// not encrypted a second time and removals stay removals. When both jars queue a cookie with
+    /// the same name, the one from `cookies` wins.
+    #[must_use]
+    pub fn extend(mut self, cookies: Self) -> Self {
+        for cookie in cookies.jar.delta() {
+            if is_removal(cookie) {
+                self.jar
+                    .add_original(Cookie::new(cookie.name().to_owned(), ""));
+                self.jar.remove(cookie.clone());
+            } else {
+                self.jar.add(cookie.clone());
+            }
         }
-        jar
+        self
+    }
+
+    /// Returns the `Set-Cookie` cookies queued in this jar, signed or encrypted as they will be
+    /// sent.
+    pub fn queued(&self) -> impl Iterator<Item = &Cookie<'static>> {
+        self.jar.delta()
     }
 
     pub fn from(
@@ -76,38 +339,46 @@ impl CookieJar {
         let mut jar = CookieJar {
             jar: cookie::CookieJar::new(),
             key,
+            previous_keys: Arc::new([]),
             encryption_policy: policy,
+            config: Arc::default(),
+            consent: CookieConsent::default(),
+            layered: false,
         };
         jar.from_headers(headers)
     }
 
+    /// Seeds the jar with the cookies of a request.
+    ///
+    /// Cookies are stored exactly as received, signed and private cookies are only verified or
+    /// decrypted when read with [`get`](Self::get).
     pub fn from_headers(&mut self, headers: &HeaderMap) -> Self {
-        for cookie in typed_cookies_from_request(headers, &self.encryption_policy) {
-            match cookie.kind() {
-                CookieKind::Normal => {
-                    self.jar.add_original(cookie.into_cookie());
-                }
-                CookieKind::Private => {
-                    self.jar
-                        .private_mut(&self.key)
-                        .add_original(cookie.into_cookie());
-                }
-                CookieKind::Signed => {
-                    self.jar
-                        .signed_mut(&self.key)
-                        .add_original(cookie.into_cookie());
-                }
-            }
+        for cookie in cookies_from_request(headers) {
+            self.jar.add_original(cookie);
+        }
+        if let Some(policy) = &self.config.consent {
+            self.consent = self
+                .get(policy.cookie_name().to_owned())
+                .map(|cookie| CookieConsent::parse(cookie.value()))
+                .unwrap_or_default();
         }
         Self {
             // Hashsets are empty so cheap clone
             jar: self.jar.clone(),
             key: self.key.clone(),
+            previous_keys: self.previous_keys.clone(),
             encryption_policy: self.encryption_policy.clone(),
+            config: self.config.clone(),
+            consent: self.consent,
+            layered: self.layered,
         }
     }
 }
 
+fn is_removal(cookie: &Cookie<'_>) -> bool {
+    cookie.max_age() == Some(Duration::ZERO)
+}
+
 #[derive(Debug)]
 pub struct TypedCookie<'a> {
     cookie: Cookie<'a>,
@@ -129,7 +400,7 @@ pub fn typed_cookies_from_request<'a>(
     policy: &'a EncryptionCookiePolicy,
 ) -> impl Iterator<Item = TypedCookie<'static>> + 'a {
     cookies_from_request(headers).map(move |cookie| {
-        let kind = policy.cookie_kind(cookie.name().to_owned());
+        let kind = policy.cookie_kind(cookie.name());
         TypedCookie { cookie, kind }
     })
 }
@@ -199,7 +470,11 @@ mod tests {
         let mut jar = CookieJar {
             jar: cookie::CookieJar::new(),
             key: key.into(),
+            previous_keys: Arc::new([]),
             encryption_policy: policy.into(),
+            config: Arc::default(),
+            consent: CookieConsent::default(),
+            layered: false,
         };
 
         let mut headers = HeaderMap::new();
@@ -231,7 +506,11 @@ mod tests {
         let mut jar = CookieJar {
             jar: cookie::CookieJar::new(),
             key: key.clone().into(),
+            previous_keys: Arc::new([]),
             encryption_policy: policy.into(),
+            config: Arc::default(),
+            consent: CookieConsent::default(),
+            layered: false,
         };
 
         let mut headers = HeaderMap::new();
@@ -245,8 +524,8 @@ mod tests {
         let jar = jar.from_headers(&headers);
 
         let in_private = jar.jar.private(&key).get("id").unwrap();
-        let decrypt_cookie = jar.jar.private(&key).decrypt(in_private.clone()).unwrap();
-        assert_eq!(decrypt_cookie.value(), "1234");
+        assert_eq!(in_private.value(), "1234");
+        assert_eq!(jar.get("id").unwrap().value(), "1234");
 
         let theme = jar.jar.get("theme").unwrap();
         assert_eq!(theme.value(), "light");
@@ -285,7 +564,11 @@ mod tests {
         let mut jar = CookieJar {
             jar: cookie::CookieJar::new(),
             key: key.clone().into(),
+            previous_keys: Arc::new([]),
             encryption_policy: policy.into(),
+            config: Arc::default(),
+            consent: CookieConsent::default(),
+            layered: false,
         };
 
         let mut headers = HeaderMap::new();
@@ -299,10 +582,295 @@ mod tests {
         let jar = jar.from_headers(&headers);
 
         let in_signed = jar.jar.signed(&key).get("id").unwrap();
-        let verify_cookie = jar.jar.signed(&key).verify(in_signed.clone()).unwrap();
-        assert_eq!(verify_cookie.value(), "1234");
+        assert_eq!(in_signed.value(), "1234");
+        assert_eq!(jar.get("id").unwrap().value(), "1234");
 
         let theme = jar.jar.get("theme").unwrap();
         assert_eq!(theme.value(), "light");
     }
+
+    fn set_cookies(jar: &CookieJar) -> Vec<Cookie<'static>> {
+        jar.jar.delta().cloned().collect()
+    }
+
+    #[test]
+    fn test_remove_expires_cookie() {
+        let key = cookie::Key::generate();
+        let mut policy = EncryptionCookiePolicy::default();
+        policy.insert("id", CookieKind::Private);
+
+        let id = create_private_cookie_value(&key, "id", "1234");
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, format!("id={}", id).parse().unwrap());
+
+        let mut jar = CookieJar::builder(key)
+            .with_encryption_policy(policy)
+            .with_default_path("/")
+            .with_default_domain("example.com")
+            .build();
+        let jar = jar.from_headers(&headers).remove("id").forget("theme");
+
+        assert!(jar.get("id").is_none());
+        let removals = set_cookies(&jar);
+        assert_eq!(removals.len(), 2);
+        for cookie in removals {
+            assert_eq!(cookie.value(), "");
+            assert_eq!(cookie.max_age(), Some(Duration::ZERO));
+            assert_eq!(cookie.path(), Some("/"));
+            assert_eq!(cookie.domain(), Some("example.com"));
+        }
+    }
+
+    #[test]
+    fn test_remove_keeps_cookie_path() {
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .with_default_path("/")
+            .build()
+            .insert(Cookie::build(("theme", "dark")).path("/admin").build())
+            .remove("theme");
+
+        let removal = &set_cookies(&jar)[0];
+        assert_eq!(removal.path(), Some("/admin"));
+        assert_eq!(removal.max_age(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_jar_defaults() {
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .with_default_path("/")
+            .with_default_secure(true)
+            .with_default_same_site(SameSite::Lax)
+            .build()
+            .insert(Cookie::new("theme", "dark"))
+            .forever(
+                Cookie::build(("locale", "en"))
+                    .same_site(SameSite::Strict)
+                    .build(),
+            );
+
+        let theme = jar.get("theme").unwrap();
+        assert_eq!(theme.path(), Some("/"));
+        assert_eq!(theme.secure(), Some(true));
+        assert_eq!(theme.same_site(), Some(SameSite::Lax));
+
+        let locale = jar.get("locale").unwrap();
+        assert_eq!(locale.same_site(), Some(SameSite::Strict));
+        assert!(locale.max_age().unwrap() > Duration::days(365));
+    }
+
+    #[test]
+    fn test_extend_keeps_queued_cookies() {
+        let key = cookie::Key::generate();
+        let mut policy = EncryptionCookiePolicy::default();
+        policy.insert("id", CookieKind::Private);
+        let jar = CookieJar::builder(key)
+            .with_encryption_policy(policy)
+            .build();
+
+        let first = jar
+            .clone()
+            .insert(Cookie::new("id", "1234"))
+            .insert(Cookie::new("theme", "dark"));
+        let second = jar.insert(Cookie::new("theme", "light")).remove("locale");
+
+        let merged = first.extend(second);
+
+        assert_eq!(merged.queued().count(), 3);
+        assert_eq!(merged.get("id").unwrap().value(), "1234");
+        assert_eq!(merged.get("theme").unwrap().value(), "light");
+        assert!(merged.get("locale").is_none());
+        assert!(merged
+            .queued()
+            .any(|cookie| cookie.name() == "locale" && is_removal(cookie)));
+    }
+
+    fn consent_jar(cookie: &str) -> CookieJar {
+        let mut policy = ConsentPolicy::new("consent");
+        policy.insert("theme", CookieCategory::Preferences);
+        policy.insert_prefix("_ga", CookieCategory::Analytics);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, cookie.parse().unwrap());
+
+        CookieJar::builder(cookie::Key::generate())
+            .with_consent_policy(policy)
+            .build()
+            .from_headers(&headers)
+    }
+
+    #[test]
+    fn test_non_consented_cookies_are_dropped() {
+        let jar = consent_jar("consent=preferences");
+        assert!(jar.consent().allows(CookieCategory::Preferences));
+
+        let jar = jar
+            .insert(Cookie::new("theme", "dark"))
+            .insert(Cookie::new("_ga_id", "1"))
+            .insert(Cookie::new("id", "1234"));
+
+        assert!(jar.get("theme").is_some());
+        assert!(jar.get("_ga_id").is_none());
+        assert!(jar.get("id").is_some());
+
+        let err = jar.clone().try_insert(Cookie::new("_ga", "1")).unwrap_err();
+        assert!(matches!(
+            err,
+            CookieError::NotConsented {
+                category: CookieCategory::Analytics,
+                ..
+            }
+        ));
+
+        let jar = jar.try_insert_essential(Cookie::new("_ga", "1")).unwrap();
+        assert_eq!(jar.get("_ga").unwrap().value(), "1");
+    }
+
+    #[test]
+    fn test_grant_consent() {
+        let jar = consent_jar("_ga=1; theme=dark");
+        assert!(!jar.allows("theme"));
+
+        let expired = jar.clone().expire_non_consented();
+        let mut names: Vec<_> = set_cookies(&expired)
+            .iter()
+            .map(|cookie| cookie.name().to_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["_ga", "theme"]);
+
+        let jar = jar
+            .grant_consent(CookieConsent::essential().with(CookieCategory::Analytics))
+            .insert(Cookie::new("_ga", "2"));
+        assert_eq!(jar.get("consent").unwrap().value(), "analytics");
+        assert_eq!(jar.get("_ga").unwrap().value(), "2");
+    }
+
+    #[test]
+    fn test_prefixed_cookies() {
+        let cookie = || {
+            Cookie::build(("__Host-id", "1234"))
+                .domain("example.com")
+                .build()
+        };
+
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .build()
+            .insert(cookie())
+            .remove("__Secure-theme");
+        let id = jar.get("__Host-id").unwrap();
+        assert_eq!(id.secure(), Some(true));
+        assert_eq!(id.path(), Some("/"));
+        assert_eq!(id.domain(), None);
+        let removal = jar.jar.delta().find(|c| c.name() == "__Secure-theme");
+        assert_eq!(removal.unwrap().secure(), Some(true));
+
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .with_prefix_enforcement(PrefixEnforcement::Reject)
+            .build();
+        assert!(matches!(
+            jar.clone().try_insert(cookie()),
+            Err(CookieError::InvalidPrefix {
+                violation: PrefixViolation::NotSecure,
+                ..
+            })
+        ));
+        assert!(jar.insert(cookie()).get("__Host-id").is_none());
+    }
+
+    #[test]
+    fn test_size_limit_applies_after_encryption() {
+        let mut policy = EncryptionCookiePolicy::default();
+        policy.insert("id", CookieKind::Private);
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .with_encryption_policy(policy)
+            .with_max_cookie_size(64)
+            .build();
+
+        let value = "a".repeat(40);
+        let jar = jar
+            .insert(Cookie::new("theme", value.clone()))
+            .insert(Cookie::new("id", value.clone()));
+        assert!(jar.get("theme").is_some());
+        assert!(jar.get("id").is_none());
+
+        let err = jar.try_insert(Cookie::new("id", value)).unwrap_err();
+        assert!(matches!(err, CookieError::TooLarge { size, limit: 64, .. } if size > 64));
+    }
+
+    #[test]
+    fn test_count_limit() {
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .with_max_cookie_count(2)
+            .build()
+            .insert(Cookie::new("a", "1"))
+            .insert(Cookie::new("b", "1"))
+            .insert(Cookie::new("a", "2"));
+        assert_eq!(jar.get("a").unwrap().value(), "2");
+
+        let err = jar.try_insert(Cookie::new("c", "1")).unwrap_err();
+        assert!(matches!(
+            err,
+            CookieError::TooMany {
+                count: 2,
+                limit: 2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old_key = cookie::Key::generate();
+        let mut policy = EncryptionCookiePolicy::default();
+        policy.insert("id", CookieKind::Private);
+        policy.insert("csrftoken", CookieKind::Signed);
+
+        let id = create_private_cookie_value(&old_key, "id", "1234");
+        let csrftoken = create_signed_cookie_value(&old_key, "csrftoken", "5678");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            format!("id={}; csrftoken={}", id, csrftoken)
+                .parse()
+                .unwrap(),
+        );
+
+        let mut jar = CookieJar::builder(cookie::Key::generate())
+            .with_previous_keys([old_key])
+            .with_encryption_policy(policy)
+            .build();
+        let jar = jar.from_headers(&headers);
+
+        assert_eq!(jar.get("id").unwrap().value(), "1234");
+        assert_eq!(jar.get("csrftoken").unwrap().value(), "5678");
+        assert!(jar.needs_reissue("id"));
+        assert!(jar.needs_reissue("csrftoken"));
+        assert_eq!(jar.stale().len(), 2);
+
+        let jar = jar.reissue_stale();
+        assert!(!jar.needs_reissue("id"));
+        assert_eq!(jar.get("id").unwrap().value(), "1234");
+        assert_eq!(jar.jar.delta().count(), 2);
+    }
+
+    #[test]
+    fn test_unknown_key_is_rejected() {
+        let mut policy = EncryptionCookiePolicy::default();
+        policy.insert("id", CookieKind::Private);
+
+        let id = create_private_cookie_value(&cookie::Key::generate(), "id", "1234");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, format!("id={}", id).parse().unwrap());
+
+        let mut jar = CookieJar::builder(cookie::Key::generate())
+            .with_previous_keys([cookie::Key::generate()])
+            .with_encryption_policy(policy)
+            .build();
+        let jar = jar.from_headers(&headers);
+
+        assert!(jar.get("id").is_none());
+        assert!(!jar.needs_reissue("id"));
+    }
 }
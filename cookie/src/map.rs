@@ -1,29 +1,149 @@
 use std::{borrow::Cow, collections::HashMap};
 
-use crate::CookieKind;
+use crate::{
+    pattern::{CookiePattern, CookiePredicate},
+    CookieKind,
+};
 
 pub type CookieKey = Cow<'static, str>;
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
-pub struct CookieMap {
-    data: HashMap<CookieKey, CookieKind>,
+/// Maps cookie names to the kind of cookie they are, or to any other value such as their
+/// [`CookieCategory`](crate::CookieCategory).
+///
+/// Names are resolved with the following precedence:
+/// 1. an exact name inserted with [`insert`](Self::insert);
+/// 2. the longest prefix inserted with [`insert_prefix`](Self::insert_prefix);
+/// 3. the first glob pattern inserted with [`insert_pattern`](Self::insert_pattern);
+/// 4. the first predicate inserted with [`insert_predicate`](Self::insert_predicate).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieMap<V = CookieKind> {
+    data: HashMap<CookieKey, V>,
+    /// Sorted from the longest to the shortest prefix.
+    prefixes: Vec<(CookieKey, V)>,
+    patterns: Vec<(CookiePattern, V)>,
+    predicates: Vec<(CookiePredicate, V)>,
+}
+
+impl<V> Default for CookieMap<V> {
+    fn default() -> Self {
+        Self {
+            data: HashMap::new(),
+            prefixes: Vec::new(),
+            patterns: Vec::new(),
+            predicates: Vec::new(),
+        }
+    }
 }
 
 impl CookieMap {
     pub fn new() -> Self {
         Self::default()
     }
+}
 
-    pub fn insert<I: Into<CookieKey>>(&mut self, key: I, kind: CookieKind) -> &mut Self {
+impl<V: Copy> CookieMap<V> {
+    pub fn insert<I: Into<CookieKey>>(&mut self, key: I, kind: V) -> &mut Self {
         self.data.insert(key.into(), kind);
         self
     }
 
-    pub fn get(&self, key: &CookieKey) -> Option<CookieKind> {
+    /// Maps every cookie whose name starts with `prefix`.
+    pub fn insert_prefix<I: Into<CookieKey>>(&mut self, prefix: I, kind: V) -> &mut Self {
+        let prefix = prefix.into();
+        self.prefixes.retain(|(existing, _)| *existing != prefix);
+        let index = self
+            .prefixes
+            .partition_point(|(existing, _)| existing.len() >= prefix.len());
+        self.prefixes.insert(index, (prefix, kind));
+        self
+    }
+
+    /// Maps every cookie whose name matches the glob `pattern`, see [`CookiePattern`].
+    pub fn insert_pattern<I: Into<CookieKey>>(&mut self, pattern: I, kind: V) -> &mut Self {
+        self.patterns.push((CookiePattern::new(pattern), kind));
+        self
+    }
+
+    /// Maps every cookie whose name satisfies `predicate`.
+    pub fn insert_predicate<F>(&mut self, predicate: F, kind: V) -> &mut Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.predicates
+            .push((CookiePredicate::new(predicate), kind));
+        self
+    }
+
+    pub fn get(&self, key: &CookieKey) -> Option<V> {
         self.data.get(key).copied()
     }
 
     pub fn has(&self, key: &CookieKey) -> bool {
         self.data.contains_key(key)
     }
+
+    /// Resolves the value of the cookie named `name` using every rule of the map.
+    pub fn resolve(&self, name: &str) -> Option<V> {
+        if let Some(kind) = self.data.get(name) {
+            return Some(*kind);
+        }
+
+        let prefix = self
+            .prefixes
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix.as_ref()));
+        let pattern = || {
+            self.patterns
+                .iter()
+                .find(|(pattern, _)| pattern.matches(name))
+        };
+        let predicate = || {
+            self.predicates
+                .iter()
+                .find(|(predicate, _)| predicate.matches(name))
+                .map(|(_, kind)| *kind)
+        };
+
+        prefix
+            .map(|(_, kind)| *kind)
+            .or_else(|| pattern().map(|(_, kind)| *kind))
+            .or_else(predicate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_precedence() {
+        let mut cookies = CookieMap::new();
+        cookies
+            .insert("pref_theme", CookieKind::Normal)
+            .insert_prefix("pref_", CookieKind::Signed)
+            .insert_prefix("pref_secret_", CookieKind::Private)
+            .insert_pattern("*_token", CookieKind::Signed)
+            .insert_predicate(|name| name.len() > 20, CookieKind::Private);
+
+        assert_eq!(cookies.resolve("pref_theme"), Some(CookieKind::Normal));
+        assert_eq!(cookies.resolve("pref_lang"), Some(CookieKind::Signed));
+        assert_eq!(cookies.resolve("pref_secret_id"), Some(CookieKind::Private));
+        assert_eq!(cookies.resolve("pref_token"), Some(CookieKind::Signed));
+        assert_eq!(cookies.resolve("csrf_token"), Some(CookieKind::Signed));
+        assert_eq!(
+            cookies.resolve("a_very_long_cookie_name"),
+            Some(CookieKind::Private)
+        );
+        assert_eq!(cookies.resolve("theme"), None);
+    }
+
+    #[test]
+    fn test_prefix_replaced() {
+        let mut cookies = CookieMap::new();
+        cookies
+            .insert_prefix("pref_", CookieKind::Signed)
+            .insert_prefix("pref_", CookieKind::Private);
+
+        assert_eq!(cookies.resolve("pref_lang"), Some(CookieKind::Private));
+    }
 }
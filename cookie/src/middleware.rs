@@ -1,5 +1,6 @@
 use std::{
     convert::Infallible,
+    ops::Deref,
     task::{Context, Poll},
 };
 
@@ -7,11 +8,13 @@ use axum_core::{
     extract::{self, FromRequestParts},
     response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
 };
-use http::{header, request::Parts, HeaderMap};
+use http::{header, request::Parts, Extensions, HeaderMap};
 use tower_layer::Layer;
 use tower_service::Service;
 
-use crate::CookieJar;
+use cookie::Key;
+
+use crate::{CookieJar, CookieJarMissingFromExt, EncryptionCookiePolicy};
 
 #[derive(Debug, Clone)]
 pub struct CookieMidleware<S> {
@@ -63,14 +66,15 @@ where
     }
 
     fn call(&mut self, mut req: extract::Request) -> Self::Future {
-        println!("CookieMidleware::call");
-        let headers = req.headers();
-        let jar = self.jar.from_headers(headers);
-        req.extensions_mut().insert(jar.clone());
+        let mut jar = self.jar.clone().from_headers(req.headers());
+        jar.layered = true;
+        let expired = jar.clone().expire_non_consented();
+        req.extensions_mut().insert(jar);
 
         ResponseFuture {
             future: self.inner.call(req),
-            cookie_jar: jar,
+            expired: (expired.queued().count() > 0).then_some(expired),
+            max_set_cookie_size: self.jar.config.max_set_cookie_size,
         }
     }
 }
@@ -80,14 +84,87 @@ impl<S> FromRequestParts<S> for CookieJar
 where
     S: Send + Sync,
 {
-    type Rejection = Infallible;
+    type Rejection = CookieJarMissingFromExt;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        Ok(parts
+        parts
             .extensions
             .get::<CookieJar>()
             .cloned()
-            .expect("the cookie jar is missing"))
+            .ok_or(CookieJarMissingFromExt)
+    }
+}
+
+/// Extracts the `CookieJar` installed by the `CookieLayer`, or a plain jar built from the request
+/// headers when no layer is installed.
+///
+/// A plain jar neither signs nor encrypts cookies, and since no layer is there to emit its
+/// queued cookies, returning it writes the `Set-Cookie` headers directly.
+#[derive(Debug, Clone)]
+pub struct HeaderCookieJar {
+    jar: CookieJar,
+}
+
+impl HeaderCookieJar {
+    /// Returns `true` if the jar was installed by the `CookieLayer`.
+    pub fn is_layered(&self) -> bool {
+        self.jar.layered
+    }
+
+    pub fn into_inner(self) -> CookieJar {
+        self.jar
+    }
+
+    /// Applies `f` to the inner jar, keeping track of where the jar came from.
+    #[must_use]
+    pub fn map<F>(self, f: F) -> Self
+    where
+        F: FnOnce(CookieJar) -> CookieJar,
+    {
+        Self { jar: f(self.jar) }
+    }
+}
+
+impl Deref for HeaderCookieJar {
+    type Target = CookieJar;
+
+    fn deref(&self) -> &Self::Target {
+        &self.jar
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for HeaderCookieJar
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header_cookie_jar = match parts.extensions.get::<CookieJar>() {
+            Some(jar) => Self { jar: jar.clone() },
+            None => Self {
+                jar: CookieJar::builder(Key::generate())
+                    .with_encryption_policy(EncryptionCookiePolicy::inclusion())
+                    .build()
+                    .from_headers(&parts.headers),
+            },
+        };
+        Ok(header_cookie_jar)
+    }
+}
+
+impl IntoResponseParts for HeaderCookieJar {
+    type Error = Infallible;
+
+    fn into_response_parts(self, res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        self.jar.into_response_parts(res)
+    }
+}
+
+impl IntoResponse for HeaderCookieJar {
+    fn into_response(self) -> Response {
+        (self, ()).into_response()
     }
 }
 
@@ -96,7 +173,9 @@ pin_project_lite::pin_project! {
     pub struct ResponseFuture<F> {
         #[pin]
         future: F,
-        cookie_jar: CookieJar,
+        // Removals of the request cookies the user did not consent to.
+        expired: Option<CookieJar>,
+        max_set_cookie_size: Option<usize>,
     }
 }
 
@@ -118,25 +197,55 @@ where
             Err(err) => err.into_response(),
         };
 
-        res.extensions_mut().insert(this.cookie_jar.clone());
+        let queued = res
+            .extensions_mut()
+            .remove::<QueuedCookies>()
+            .map(|QueuedCookies(jar)| jar);
+        let jar = match (this.expired.take(), queued) {
+            (Some(expired), Some(queued)) => Some(expired.extend(queued)),
+            (expired, queued) => expired.or(queued),
+        };
+        if let Some(jar) = jar {
+            set_cookies(&jar, res.headers_mut());
+        }
+        if let Some(limit) = *this.max_set_cookie_size {
+            check_set_cookie_size(res.headers(), limit);
+        }
 
         Poll::Ready(Ok(res))
     }
 }
 
+/// The cookies queued by a response, emitted by the `CookieLayer` once the inner service is done.
+#[derive(Debug, Clone)]
+struct QueuedCookies(CookieJar);
+
+impl CookieJar {
+    /// Queues the cookies of this jar in `extensions`, merging them with any cookies already
+    /// queued by the response.
+    ///
+    /// The `CookieLayer` turns the queued cookies into `Set-Cookie` headers exactly once, so
+    /// handlers and nested middleware can each return a jar without duplicating headers.
+    pub fn queue(self, extensions: &mut Extensions) {
+        let jar = match extensions.remove::<QueuedCookies>() {
+            Some(QueuedCookies(queued)) => queued.extend(self),
+            None => self,
+        };
+        extensions.insert(QueuedCookies(jar));
+    }
+}
+
+/// Queues the cookies for the `CookieLayer` when the jar came from it, and otherwise writes the
+/// `Set-Cookie` headers directly since no layer may be there to emit them.
 impl IntoResponseParts for CookieJar {
     type Error = Infallible;
 
     fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
-        let jar = if let Some(value) = res.extensions_mut().remove::<CookieJar>() {
-            let jar = self.extend(value);
-            res.extensions_mut().insert(jar.clone());
-            jar.jar
+        if self.layered {
+            self.queue(res.extensions_mut());
         } else {
-            res.extensions_mut().insert(self.clone());
-            self.jar
-        };
-        set_cookies(jar, res.headers_mut());
+            set_cookies(&self, res.headers_mut());
+        }
         Ok(res)
     }
 }
@@ -147,10 +256,222 @@ impl IntoResponse for CookieJar {
     }
 }
 
-fn set_cookies(jar: cookie::CookieJar, headers: &mut HeaderMap) {
-    for cookie in jar.delta() {
+fn set_cookies(jar: &CookieJar, headers: &mut HeaderMap) {
+    for cookie in jar.queued() {
         if let Ok(header_value) = cookie.encoded().to_string().parse() {
             headers.append(header::SET_COOKIE, header_value);
         }
     }
 }
+
+/// Warns when the `Set-Cookie` headers add up to more than `limit` bytes.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn check_set_cookie_size(headers: &HeaderMap, limit: usize) {
+    #[cfg(feature = "tracing")]
+    {
+        let values = headers.get_all(header::SET_COOKIE);
+        let size: usize = values.iter().map(|value| value.len()).sum();
+        if size > limit {
+            tracing::warn!(
+                size,
+                limit,
+                count = values.iter().count(),
+                "Set-Cookie headers exceed the configured size"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum_core::body::Body;
+    use cookie::Cookie;
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+    use crate::{ConsentPolicy, CookieCategory, CookieKind};
+
+    fn layer(key: &cookie::Key) -> CookieLayer {
+        let mut policy = EncryptionCookiePolicy::default();
+        policy.insert("id", CookieKind::Private);
+        CookieLayer::new(
+            CookieJar::builder(key.clone())
+                .with_encryption_policy(policy)
+                .build(),
+        )
+    }
+
+    fn set_cookie_headers(response: &Response) -> Vec<Cookie<'static>> {
+        response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .into_iter()
+            .map(|value| Cookie::parse_encoded(value.to_str().unwrap().to_owned()).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_cookies_are_emitted_once() {
+        let key = cookie::Key::generate();
+        let service = layer(&key).layer(service_fn(|req: extract::Request| async move {
+            let jar = req.extensions().get::<CookieJar>().cloned().unwrap();
+            let first = jar.clone().insert(Cookie::new("id", "1234"));
+            let second = jar.insert(Cookie::new("theme", "dark")).remove("locale");
+            Ok::<_, Infallible>((first, second, ()).into_response())
+        }));
+
+        let request = extract::Request::builder()
+            .header(header::COOKIE, "locale=en")
+            .body(Body::empty())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+
+        let cookies = set_cookie_headers(&response);
+        assert_eq!(cookies.len(), 3);
+        assert!(response.extensions().get::<QueuedCookies>().is_none());
+
+        let id = cookies.iter().find(|cookie| cookie.name() == "id").unwrap();
+        let mut jar = cookie::CookieJar::new();
+        jar.add_original(id.clone());
+        assert_eq!(jar.private(&key).get("id").unwrap().value(), "1234");
+
+        let locale = cookies
+            .iter()
+            .find(|cookie| cookie.name() == "locale")
+            .unwrap();
+        assert_eq!(locale.value(), "");
+    }
+
+    #[tokio::test]
+    async fn test_nested_middleware_overrides_handler() {
+        let key = cookie::Key::generate();
+        let handler = service_fn(|req: extract::Request| async move {
+            let jar = req.extensions().get::<CookieJar>().cloned().unwrap();
+            Ok::<_, Infallible>((jar.insert(Cookie::new("theme", "dark")), ()).into_response())
+        });
+        let nested = service_fn(move |req: extract::Request| {
+            let jar = req.extensions().get::<CookieJar>().cloned().unwrap();
+            async move {
+                let response = handler.oneshot(req).await?;
+                Ok::<_, Infallible>(
+                    (jar.insert(Cookie::new("theme", "light")), response).into_response(),
+                )
+            }
+        });
+
+        let request = extract::Request::builder().body(Body::empty()).unwrap();
+        let response = layer(&key).layer(nested).oneshot(request).await.unwrap();
+
+        let cookies = set_cookie_headers(&response);
+        assert_eq!(cookies.len(), 1);
+        assert_eq!(cookies[0].value(), "light");
+    }
+
+    #[tokio::test]
+    async fn test_missing_jar_is_rejected() {
+        let (mut parts, _) = extract::Request::new(Body::empty()).into_parts();
+        let rejection = CookieJar::from_request_parts(&mut parts, &())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            rejection.into_response().status(),
+            http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_header_jar_without_layer() {
+        let (mut parts, _) = extract::Request::builder()
+            .header(header::COOKIE, "theme=dark")
+            .body(Body::empty())
+            .unwrap()
+            .into_parts();
+        let jar = HeaderCookieJar::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+
+        assert!(!jar.is_layered());
+        assert_eq!(jar.get("theme").unwrap().value(), "dark");
+
+        let response = jar
+            .map(|jar| jar.insert(Cookie::new("theme", "light")))
+            .into_response();
+        let cookies = set_cookie_headers(&response);
+        assert_eq!(cookies.len(), 1);
+        assert_eq!(cookies[0].value(), "light");
+    }
+
+    #[tokio::test]
+    async fn test_header_jar_with_layer() {
+        let key = cookie::Key::generate();
+        let service = layer(&key).layer(service_fn(|req: extract::Request| async move {
+            let (mut parts, _) = req.into_parts();
+            let jar = HeaderCookieJar::from_request_parts(&mut parts, &())
+                .await
+                .unwrap();
+            assert!(jar.is_layered());
+            let jar = jar.map(|jar| jar.insert(Cookie::new("id", "1234")));
+            Ok::<_, Infallible>((jar.clone(), jar).into_response())
+        }));
+
+        let request = extract::Request::builder().body(Body::empty()).unwrap();
+        let response = service.oneshot(request).await.unwrap();
+
+        let cookies = set_cookie_headers(&response);
+        assert_eq!(cookies.len(), 1);
+        assert_ne!(cookies[0].value(), "1234");
+    }
+
+    #[tokio::test]
+    async fn test_jar_without_layer_sets_cookies() {
+        let service = service_fn(|_req: extract::Request| async move {
+            let jar = CookieJar::builder(cookie::Key::generate())
+                .with_encryption_policy(EncryptionCookiePolicy::inclusion())
+                .build();
+            Ok::<_, Infallible>(jar.insert(Cookie::new("theme", "dark")).into_response())
+        });
+
+        let request = extract::Request::builder().body(Body::empty()).unwrap();
+        let response = service.oneshot(request).await.unwrap();
+
+        let cookies = set_cookie_headers(&response);
+        assert_eq!(cookies.len(), 1);
+        assert_eq!(cookies[0].value(), "dark");
+        assert!(response.extensions().get::<QueuedCookies>().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_non_consented_cookies_are_expired() {
+        let mut policy = ConsentPolicy::new("consent");
+        policy.insert("_ga", CookieCategory::Analytics);
+        policy.insert("theme", CookieCategory::Preferences);
+        let layer = CookieLayer::new(
+            CookieJar::builder(cookie::Key::generate())
+                .with_consent_policy(policy)
+                .build(),
+        );
+        let service = layer.layer(service_fn(|req: extract::Request| async move {
+            let jar = req.extensions().get::<CookieJar>().cloned().unwrap();
+            Ok::<_, Infallible>((jar.insert(Cookie::new("theme", "dark")), ()).into_response())
+        }));
+
+        let request = extract::Request::builder()
+            .header(header::COOKIE, "consent=preferences; _ga=1; theme=light")
+            .body(Body::empty())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+
+        let mut cookies: Vec<_> = set_cookie_headers(&response)
+            .into_iter()
+            .map(|cookie| (cookie.name().to_owned(), cookie.value().to_owned()))
+            .collect();
+        cookies.sort();
+        assert_eq!(
+            cookies,
+            [
+                ("_ga".to_owned(), String::new()),
+                ("theme".to_owned(), "dark".to_owned())
+            ]
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::{fmt, sync::Arc};
+
+use crate::CookieKey;
+
+/// A glob pattern matched against cookie names.
+///
+/// `*` matches any sequence of characters, including an empty one, and `?` matches exactly one
+/// character. Every other character matches itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookiePattern {
+    pattern: CookieKey,
+}
+
+impl CookiePattern {
+    pub fn new<P: Into<CookieKey>>(pattern: P) -> Self {
+        Self {
+            pattern: pattern.into(),
+        }
+    }
+
+    /// Returns `true` if `name` matches the pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        let pattern = self.pattern.as_bytes();
+        let name = name.as_bytes();
+
+        let (mut p, mut n) = (0, 0);
+        // Position of the last `*` in the pattern and the name position it was tried at.
+        let mut backtrack = None;
+
+        while n < name.len() {
+            match pattern.get(p) {
+                Some(b'*') => {
+                    backtrack = Some((p, n));
+                    p += 1;
+                }
+                Some(&c) if c == b'?' || c == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match backtrack {
+                    Some((star, tried)) => {
+                        p = star + 1;
+                        n = tried + 1;
+                        backtrack = Some((star, tried + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        pattern[p..].iter().all(|&c| c == b'*')
+    }
+}
+
+/// A closure deciding whether a cookie name belongs to a rule.
+///
+/// Predicates are compared by identity, two predicates are equal only if they share the same
+/// closure.
+#[derive(Clone)]
+pub struct CookiePredicate(Arc<dyn Fn(&str) -> bool + Send + Sync>);
+
+impl CookiePredicate {
+    pub fn new<F>(predicate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        Self(Arc::new(predicate))
+    }
+
+    /// Returns `true` if `name` satisfies the predicate.
+    pub fn matches(&self, name: &str) -> bool {
+        (self.0)(name)
+    }
+}
+
+impl PartialEq for CookiePredicate {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CookiePredicate {}
+
+impl fmt::Debug for CookiePredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CookiePredicate").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let pattern = CookiePattern::new("pref_*");
+        assert!(pattern.matches("pref_"));
+        assert!(pattern.matches("pref_theme"));
+        assert!(!pattern.matches("theme"));
+
+        let pattern = CookiePattern::new("*_token");
+        assert!(pattern.matches("csrf_token"));
+        assert!(!pattern.matches("csrf_token_old"));
+
+        let pattern = CookiePattern::new("a*b*c");
+        assert!(pattern.matches("abc"));
+        assert!(pattern.matches("aXbYbZc"));
+        assert!(!pattern.matches("aXbYbZ"));
+
+        let pattern = CookiePattern::new("id_?");
+        assert!(pattern.matches("id_1"));
+        assert!(!pattern.matches("id_"));
+        assert!(!pattern.matches("id_12"));
+    }
+}
@@ -16,26 +16,47 @@ impl EncryptionCookiePolicy {
     }
 
     pub fn insert<T: Into<CookieKey>>(&mut self, key: T, kind: CookieKind) {
-        let key = key.into();
+        self.cookies_mut().insert(key, kind);
+    }
+
+    /// Applies `kind` to every cookie whose name starts with `prefix`.
+    pub fn insert_prefix<T: Into<CookieKey>>(&mut self, prefix: T, kind: CookieKind) {
+        self.cookies_mut().insert_prefix(prefix, kind);
+    }
+
+    /// Applies `kind` to every cookie whose name matches the glob `pattern`.
+    pub fn insert_pattern<T: Into<CookieKey>>(&mut self, pattern: T, kind: CookieKind) {
+        self.cookies_mut().insert_pattern(pattern, kind);
+    }
+
+    /// Applies `kind` to every cookie whose name satisfies `predicate`.
+    pub fn insert_predicate<F>(&mut self, predicate: F, kind: CookieKind)
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.cookies_mut().insert_predicate(predicate, kind);
+    }
+
+    /// Returns the kind of the cookie named `key`.
+    ///
+    /// Exact names take precedence over prefixes, then glob patterns and predicates, see
+    /// [`CookieMap`]. Cookies matching no rule fall back to the default of the policy.
+    pub fn cookie_kind<T: AsRef<str>>(&self, key: T) -> CookieKind {
+        let key = key.as_ref();
         match self {
             EncryptionCookiePolicy::Inclusion(cookies) => {
-                cookies.insert(key, kind);
+                cookies.resolve(key).unwrap_or(CookieKind::Normal)
             }
             EncryptionCookiePolicy::Exclusion(cookies) => {
-                cookies.insert(key, kind);
+                cookies.resolve(key).unwrap_or(CookieKind::Private)
             }
         }
     }
 
-    pub fn cookie_kind<T: Into<CookieKey>>(&self, key: T) -> CookieKind {
-        let key = key.into();
+    fn cookies_mut(&mut self) -> &mut CookieMap {
         match self {
-            EncryptionCookiePolicy::Inclusion(cookies) => {
-                cookies.get(&key).unwrap_or(CookieKind::Normal)
-            }
-            EncryptionCookiePolicy::Exclusion(cookies) => {
-                cookies.get(&key).unwrap_or(CookieKind::Private)
-            }
+            EncryptionCookiePolicy::Inclusion(cookies) => cookies,
+            EncryptionCookiePolicy::Exclusion(cookies) => cookies,
         }
     }
 }
@@ -116,4 +137,15 @@ mod tests {
         assert_eq!(policy.cookie_kind("csrftoken"), CookieKind::Signed);
         assert_eq!(policy.cookie_kind("theme"), CookieKind::Normal);
     }
+
+    #[test]
+    fn test_prefix_rules() {
+        let mut policy = EncryptionCookiePolicy::exclusion();
+        policy.insert_prefix("pref_", CookieKind::Signed);
+        policy.insert("pref_theme", CookieKind::Normal);
+
+        assert_eq!(policy.cookie_kind("pref_lang"), CookieKind::Signed);
+        assert_eq!(policy.cookie_kind("pref_theme"), CookieKind::Normal);
+        assert_eq!(policy.cookie_kind("session"), CookieKind::Private);
+    }
 }
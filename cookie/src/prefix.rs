@@ -0,0 +1,122 @@
+use std::fmt;
+
+use cookie::Cookie;
+
+/// A cookie name prefix that browsers enforce, see
+/// [RFC 6265bis](https://datatracker.ietf.org/doc/html/draft-ietf-httpbis-rfc6265bis#name-cookie-name-prefixes).
+///
+/// Browsers silently drop a prefixed cookie that does not meet the requirements of its prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookiePrefix {
+    /// `__Host-` cookies must be `Secure`, have `Path=/` and no `Domain`.
+    Host,
+    /// `__Secure-` cookies must be `Secure`.
+    Secure,
+}
+
+impl CookiePrefix {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Host => "__Host-",
+            Self::Secure => "__Secure-",
+        }
+    }
+
+    /// Returns the prefix of the cookie named `name`, if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Host, Self::Secure]
+            .into_iter()
+            .find(|prefix| name.starts_with(prefix.as_str()))
+    }
+
+    /// Checks that `cookie` meets the requirements of this prefix.
+    ///
+    /// # Errors
+    /// Returns the first requirement the cookie violates.
+    pub fn validate(&self, cookie: &Cookie<'_>) -> Result<(), PrefixViolation> {
+        if cookie.secure() != Some(true) {
+            return Err(PrefixViolation::NotSecure);
+        }
+        if *self == Self::Host {
+            if cookie.domain().is_some() {
+                return Err(PrefixViolation::HasDomain);
+            }
+            if cookie.path() != Some("/") {
+                return Err(PrefixViolation::PathNotRoot);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adjusts `cookie` so it meets the requirements of this prefix.
+    pub fn normalize(&self, cookie: &mut Cookie<'_>) {
+        cookie.set_secure(true);
+        if *self == Self::Host {
+            cookie.unset_domain();
+            cookie.set_path("/");
+        }
+    }
+}
+
+impl fmt::Display for CookiePrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A requirement of a [`CookiePrefix`] that a cookie does not meet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum PrefixViolation {
+    #[error("the cookie is not Secure")]
+    NotSecure,
+    #[error("the cookie sets a Domain")]
+    HasDomain,
+    #[error("the cookie Path is not /")]
+    PathNotRoot,
+}
+
+/// What a `CookieJar` does with a prefixed cookie that violates the requirements of its prefix.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrefixEnforcement {
+    /// Adjust the cookie so it meets the requirements.
+    #[default]
+    Normalize,
+    /// Refuse the cookie.
+    Reject,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_prefix() {
+        let mut cookie = Cookie::build(("__Host-id", "1234"))
+            .domain("example.com")
+            .path("/admin")
+            .build();
+        let prefix = CookiePrefix::from_name(cookie.name()).unwrap();
+
+        assert_eq!(prefix, CookiePrefix::Host);
+        assert_eq!(prefix.validate(&cookie), Err(PrefixViolation::NotSecure));
+
+        prefix.normalize(&mut cookie);
+        assert_eq!(prefix.validate(&cookie), Ok(()));
+        assert_eq!(cookie.domain(), None);
+        assert_eq!(cookie.path(), Some("/"));
+    }
+
+    #[test]
+    fn test_secure_prefix() {
+        let mut cookie = Cookie::build(("__Secure-id", "1234"))
+            .domain("example.com")
+            .build();
+        let prefix = CookiePrefix::from_name(cookie.name()).unwrap();
+
+        assert_eq!(prefix, CookiePrefix::Secure);
+        prefix.normalize(&mut cookie);
+        assert_eq!(prefix.validate(&cookie), Ok(()));
+        assert_eq!(cookie.domain(), Some("example.com"));
+        assert_eq!(CookiePrefix::from_name("id"), None);
+    }
+}
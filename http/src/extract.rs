@@ -0,0 +1,192 @@
+//! Extractors for the client address and peer information.
+use axum::{
+    extract::ConnectInfo,
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_core::extract::FromRequestParts;
+use thiserror::Error;
+
+use crate::{
+    forwarded::EffectiveOrigin,
+    ip::{ClientInfo, ClientIp},
+};
+
+/// Rejection used when the `ClientIp` is missing, because the `TrustedProxyLayer` is not
+/// installed or the server was not started with `ClientInfo` connect info.
+#[derive(Debug, Error)]
+#[error("Client IP extension is missing")]
+pub struct ClientIpMissing;
+
+impl IntoResponse for ClientIpMissing {
+    fn into_response(self) -> Response {
+        (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+    }
+}
+
+/// Rejection used when the server was not started with `ClientInfo` connect info.
+#[derive(Debug, Error)]
+#[error("Client info extension is missing")]
+pub struct ClientInfoMissing;
+
+impl IntoResponse for ClientInfoMissing {
+    fn into_response(self) -> Response {
+        (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+    }
+}
+
+/// Rejection used when the `EffectiveOrigin` is missing because the `TrustedProxyLayer` is not
+/// installed.
+#[derive(Debug, Error)]
+#[error("Effective origin extension is missing")]
+pub struct EffectiveOriginMissing;
+
+impl IntoResponse for EffectiveOriginMissing {
+    fn into_response(self) -> Response {
+        (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+    }
+}
+
+/// Extracts the client address resolved by the `TrustedProxyLayer`.
+///
+/// Use `Option<ClientIp>` when the layer may not be installed.
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = ClientIpMissing;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ClientIp>()
+            .copied()
+            .ok_or(ClientIpMissing)
+    }
+}
+
+/// Extracts the peer of the connection, which is the last proxy when behind proxies.
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = ClientInfoMissing;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ClientInfo>()
+            .copied()
+            .or_else(|| {
+                parts
+                    .extensions
+                    .get::<ConnectInfo<ClientInfo>>()
+                    .map(|info| info.0)
+            })
+            .map(|info| ClientInfo::new(*info.ip()))
+            .ok_or(ClientInfoMissing)
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for EffectiveOrigin
+where
+    S: Send + Sync,
+{
+    type Rejection = EffectiveOriginMissing;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<EffectiveOrigin>()
+            .cloned()
+            .ok_or(EffectiveOriginMissing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use axum::{body::Body, extract::Request, routing, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{ip::TrustedProxies, middleware::layer::TrustedProxyLayer};
+
+    async fn client(ip: Option<ClientIp>) -> String {
+        ip.map(|ip| ip.ip().to_string())
+            .unwrap_or_else(|| "none".to_owned())
+    }
+
+    async fn peer(info: ClientInfo, ip: ClientIp) -> String {
+        format!("{} {}", info.ip(), ip.ip())
+    }
+
+    async fn body(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    fn request(peer: IpAddr) -> Request {
+        let mut request = Request::builder()
+            .uri("/")
+            .header("x-forwarded-for", "1.1.1.1")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(ClientInfo::new(peer)));
+        request
+    }
+
+    #[tokio::test]
+    async fn test_extractors() {
+        let proxies = TrustedProxies::new().trust_private();
+        let router = Router::new()
+            .route("/", routing::get(peer))
+            .layer(TrustedProxyLayer::new(Arc::new(proxies)));
+
+        let peer = IpAddr::V6(Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped());
+        let response = router.oneshot(request(peer)).await.unwrap();
+
+        assert_eq!(body(response).await, "10.0.0.1 1.1.1.1");
+    }
+
+    #[tokio::test]
+    async fn test_optional_client_ip() {
+        let router = Router::new().route("/", routing::get(client));
+        let response = router
+            .clone()
+            .oneshot(request(IpAddr::V4(Ipv4Addr::LOCALHOST)))
+            .await
+            .unwrap();
+        assert_eq!(body(response).await, "none");
+
+        let response = router
+            .layer(TrustedProxyLayer::new(Arc::default()))
+            .oneshot(request(IpAddr::V4(Ipv4Addr::LOCALHOST)))
+            .await
+            .unwrap();
+        assert_eq!(body(response).await, "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_missing_client_ip_is_rejected() {
+        let router = Router::new().route(
+            "/",
+            routing::get(|ip: ClientIp| async move { ip.ip().to_string() }),
+        );
+        let response = router.oneshot(Request::new(Body::empty())).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body(response).await, "Client IP extension is missing");
+    }
+}
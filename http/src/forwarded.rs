@@ -0,0 +1,504 @@
+//! Resolution of the client address and origin from forwarding headers.
+//!
+//! Forwarding headers are only honoured when the peer is a trusted proxy. The chain of
+//! addresses is walked from right to left, skipping trusted proxies, and the first untrusted
+//! address is the client. Only the header set by the proxies, see [`ForwardedHeader`], is read:
+//! the others come from the client as is and would let it choose its address.
+use std::net::{IpAddr, SocketAddr};
+
+use http::{
+    header::{self, HeaderName},
+    uri::{Authority, Scheme},
+    HeaderMap,
+};
+
+use crate::ip::{ClientIp, TrustedProxies};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const X_FORWARDED_PORT: HeaderName = HeaderName::from_static("x-forwarded-port");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+
+/// The scheme and host the client used to reach the application, as reported by the nearest
+/// trusted proxy or taken from the request itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectiveOrigin {
+    scheme: Scheme,
+    authority: Option<Authority>,
+}
+
+impl EffectiveOrigin {
+    pub fn new(scheme: Scheme, authority: Option<Authority>) -> Self {
+        Self { scheme, authority }
+    }
+
+    pub fn scheme(&self) -> &Scheme {
+        &self.scheme
+    }
+
+    /// Returns the host without the port.
+    pub fn host(&self) -> Option<&str> {
+        self.authority.as_ref().map(Authority::host)
+    }
+
+    /// Returns the explicit port, or the default port of the scheme.
+    pub fn port(&self) -> Option<u16> {
+        self.authority
+            .as_ref()
+            .and_then(Authority::port_u16)
+            .or_else(|| match self.scheme.as_str() {
+                "https" => Some(443),
+                "http" => Some(80),
+                _ => None,
+            })
+    }
+
+    pub fn authority(&self) -> Option<&Authority> {
+        self.authority.as_ref()
+    }
+}
+
+/// The header the trusted proxies use to pass on the client address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ForwardedHeader {
+    /// `X-Forwarded-For`, with the origin from `X-Forwarded-Proto`, `X-Forwarded-Host` and
+    /// `X-Forwarded-Port`.
+    #[default]
+    XForwardedFor,
+    /// The RFC 7239 `Forwarded` header.
+    Forwarded,
+    /// `X-Real-IP`, which carries no origin, so the origin is taken from the request itself.
+    XRealIp,
+}
+
+/// An element of the `Forwarded` header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ForwardedElement {
+    /// The address of the node, `None` for `unknown` or obfuscated identifiers.
+    for_node: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+/// Resolves the client address and origin of a request received from `peer`, reading `header`
+/// when the peer is a trusted proxy.
+pub fn resolve(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    proxies: &TrustedProxies,
+    header: ForwardedHeader,
+) -> (ClientIp, EffectiveOrigin) {
+    let peer = peer.to_canonical();
+    if !proxies.is_trusted(&peer) {
+        return (ClientIp::new(peer), request_origin(headers));
+    }
+
+    let forwarded = match header {
+        ForwardedHeader::Forwarded => parse_forwarded(headers),
+        ForwardedHeader::XForwardedFor | ForwardedHeader::XRealIp => vec![],
+    };
+    let chain: Vec<Option<IpAddr>> = match header {
+        ForwardedHeader::Forwarded => forwarded.iter().map(|element| element.for_node).collect(),
+        ForwardedHeader::XForwardedFor => addresses(headers, &X_FORWARDED_FOR),
+        ForwardedHeader::XRealIp => addresses(headers, &X_REAL_IP),
+    };
+
+    // The index of the last hop walked, every element from it on was added by a trusted proxy.
+    let mut walked = chain.len();
+    let mut client = peer;
+    for (index, hop) in chain.iter().enumerate().rev() {
+        walked = index;
+        // An unknown or obfuscated hop cannot be trusted nor traced further.
+        let Some(ip) = hop else { break };
+        client = ip.to_canonical();
+        if !proxies.is_trusted(&client) {
+            break;
+        }
+    }
+
+    let origin = match header {
+        ForwardedHeader::Forwarded => forwarded_origin(&forwarded[walked..], headers),
+        ForwardedHeader::XForwardedFor => x_forwarded_origin(headers),
+        ForwardedHeader::XRealIp => request_origin(headers),
+    };
+
+    (ClientIp::new(client), origin)
+}
+
+/// Returns the origin from the `Host` header of the request.
+fn request_origin(headers: &HeaderMap) -> EffectiveOrigin {
+    let authority = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    EffectiveOrigin::new(Scheme::HTTP, authority)
+}
+
+/// Returns the origin from the `proto` and `host` of `elements`, the ones added by trusted proxies.
+fn forwarded_origin(elements: &[ForwardedElement], headers: &HeaderMap) -> EffectiveOrigin {
+    let fallback = request_origin(headers);
+    let scheme = elements
+        .iter()
+        .rev()
+        .find_map(|element| element.proto.as_deref())
+        .and_then(|proto| proto.parse().ok())
+        .unwrap_or(fallback.scheme);
+    let authority = elements
+        .iter()
+        .rev()
+        .find_map(|element| element.host.as_deref())
+        .and_then(|host| host.parse().ok())
+        .or(fallback.authority);
+    EffectiveOrigin::new(scheme, authority)
+}
+
+fn x_forwarded_origin(headers: &HeaderMap) -> EffectiveOrigin {
+    let fallback = request_origin(headers);
+    let scheme = last_value(headers, &X_FORWARDED_PROTO)
+        .and_then(|proto| proto.parse().ok())
+        .unwrap_or(fallback.scheme);
+    let authority = last_value(headers, &X_FORWARDED_HOST)
+        .and_then(|host| host.parse::<Authority>().ok())
+        .or(fallback.authority);
+    let port = last_value(headers, &X_FORWARDED_PORT).and_then(|port| port.parse::<u16>().ok());
+
+    let authority = match (authority, port) {
+        (Some(authority), Some(port)) => format!("{}:{}", authority.host(), port)
+            .parse()
+            .ok()
+            .or(Some(authority)),
+        (authority, _) => authority,
+    };
+    EffectiveOrigin::new(scheme, authority)
+}
+
+/// Returns the rightmost value of a comma separated header, the one set by the nearest proxy.
+fn last_value<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .rfind(|value| !value.is_empty())
+}
+
+/// Returns the addresses of a comma separated header such as `X-Forwarded-For`.
+fn addresses(headers: &HeaderMap, name: &HeaderName) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Parses the elements of the `Forwarded` header.
+///
+/// A malformed element becomes an unknown hop rather than being skipped, which would let a client
+/// hide it behind a forged element.
+fn parse_forwarded(headers: &HeaderMap) -> Vec<ForwardedElement> {
+    let mut elements = Vec::new();
+    for value in headers.get_all(header::FORWARDED) {
+        let Ok(value) = value.to_str() else {
+            elements.push(ForwardedElement::default());
+            continue;
+        };
+        for element in split_unquoted(value, ',') {
+            elements.push(parse_element(element).unwrap_or_default());
+        }
+    }
+    elements
+}
+
+fn parse_element(element: &str) -> Option<ForwardedElement> {
+    let mut parsed = ForwardedElement::default();
+    for pair in split_unquoted(element, ';') {
+        let pair = pair.trim();
+        if pair.is_empty() {
+            continue;
+        }
+        let (key, value) = pair.split_once('=')?;
+        let value = unquote(value.trim());
+        match key.trim().to_ascii_lowercase().as_str() {
+            "for" => parsed.for_node = parse_node(&value),
+            "proto" => parsed.proto = Some(value.to_ascii_lowercase()),
+            "host" => parsed.host = Some(value),
+            _ => {}
+        }
+    }
+    Some(parsed)
+}
+
+/// Parses a node such as `192.0.2.43`, `192.0.2.43:47011` or `[2001:db8:cafe::17]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|node| node.split_once(']'))
+        .and_then(|(ip, _)| ip.parse().ok())
+}
+
+/// Splits `value` on `separator`, ignoring separators inside quoted strings.
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (index, char) in value.char_indices() {
+        match char {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if char == separator && !quoted => {
+                parts.push(&value[start..index]);
+                start = index + char.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+fn unquote(value: &str) -> String {
+    match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(value) => value.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::new()
+            .trust("10.0.0.0/8".parse().unwrap())
+            .trust("2001:db8::/32".parse().unwrap())
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                    value.parse().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1"), ("host", "example.com")]);
+        let (client, origin) = resolve(
+            ip("8.8.8.8"),
+            &headers,
+            &proxies(),
+            ForwardedHeader::XForwardedFor,
+        );
+
+        assert_eq!(*client.ip(), ip("8.8.8.8"));
+        assert_eq!(origin.scheme(), &Scheme::HTTP);
+        assert_eq!(origin.host(), Some("example.com"));
+    }
+
+    #[test]
+    fn test_x_forwarded_for_skips_trusted_hops() {
+        let headers = headers(&[
+            ("x-forwarded-for", "6.6.6.6, 1.1.1.1"),
+            ("x-forwarded-for", "10.0.0.2"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "example.com"),
+            ("x-forwarded-port", "8443"),
+        ]);
+        let (client, origin) = resolve(
+            ip("10.0.0.1"),
+            &headers,
+            &proxies(),
+            ForwardedHeader::XForwardedFor,
+        );
+
+        assert_eq!(*client.ip(), ip("1.1.1.1"));
+        assert_eq!(origin.scheme(), &Scheme::HTTPS);
+        assert_eq!(origin.host(), Some("example.com"));
+        assert_eq!(origin.port(), Some(8443));
+    }
+
+    #[test]
+    fn test_x_real_ip() {
+        let headers = headers(&[("x-real-ip", "1.1.1.1")]);
+        let (client, _) = resolve(
+            ip("10.0.0.1"),
+            &headers,
+            &proxies(),
+            ForwardedHeader::XRealIp,
+        );
+
+        assert_eq!(*client.ip(), ip("1.1.1.1"));
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let headers = headers(&[
+            (
+                "forwarded",
+                r#"for=1.1.1.1;proto=http, for="[2001:db8:cafe::17]:4711";proto=https;host="example.com""#,
+            ),
+            ("x-forwarded-for", "6.6.6.6"),
+        ]);
+        let (client, origin) = resolve(
+            ip("10.0.0.1"),
+            &headers,
+            &proxies(),
+            ForwardedHeader::Forwarded,
+        );
+
+        assert_eq!(*client.ip(), ip("1.1.1.1"));
+        assert_eq!(origin.scheme(), &Scheme::HTTPS);
+        assert_eq!(origin.host(), Some("example.com"));
+        assert_eq!(origin.port(), Some(443));
+    }
+
+    #[test]
+    fn test_unknown_hop_stops_the_walk() {
+        let headers = headers(&[("forwarded", "for=1.1.1.1, for=unknown, for=10.0.0.3")]);
+        let (client, _) = resolve(
+            ip("10.0.0.1"),
+            &headers,
+            &proxies(),
+            ForwardedHeader::Forwarded,
+        );
+
+        assert_eq!(*client.ip(), ip("10.0.0.3"));
+    }
+
+    #[test]
+    fn test_malformed_forwarded_is_an_unknown_hop() {
+        for forwarded in [&b"garbage"[..], b"for=1.1.1.1;proto", b"for=\xff1.1.1.1"] {
+            let mut headers = HeaderMap::new();
+            headers.append(
+                header::FORWARDED,
+                HeaderValue::from_bytes(forwarded).unwrap(),
+            );
+            let (client, _) = resolve(
+                ip("10.0.0.1"),
+                &headers,
+                &proxies(),
+                ForwardedHeader::Forwarded,
+            );
+
+            assert_eq!(*client.ip(), ip("10.0.0.1"), "{forwarded:?}");
+        }
+
+        let headers = headers(&[("forwarded", "for=6.6.6.6, garbage, for=10.0.0.2")]);
+        let (client, _) = resolve(
+            ip("10.0.0.1"),
+            &headers,
+            &proxies(),
+            ForwardedHeader::Forwarded,
+        );
+        assert_eq!(*client.ip(), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn test_forwarded_origin_from_trusted_hops_only() {
+        let spoofed = headers(&[
+            ("host", "example.com"),
+            (
+                "forwarded",
+                "for=6.6.6.6;host=evil.example;proto=http, for=1.1.1.1;proto=https",
+            ),
+        ]);
+        let (client, origin) = resolve(
+            ip("10.0.0.1"),
+            &spoofed,
+            &proxies(),
+            ForwardedHeader::Forwarded,
+        );
+
+        assert_eq!(*client.ip(), ip("1.1.1.1"));
+        assert_eq!(origin.scheme(), &Scheme::HTTPS);
+        assert_eq!(origin.host(), Some("example.com"));
+
+        // Behind two trusted proxies, the element added by the outer one is honoured too.
+        let outer = headers(&[("forwarded", "for=1.1.1.1;host=app.example, for=10.0.0.2")]);
+        let (client, origin) = resolve(
+            ip("10.0.0.1"),
+            &outer,
+            &proxies(),
+            ForwardedHeader::Forwarded,
+        );
+        assert_eq!(*client.ip(), ip("1.1.1.1"));
+        assert_eq!(origin.host(), Some("app.example"));
+    }
+
+    #[test]
+    fn test_only_the_configured_header_is_read() {
+        // The trusted proxy appends to `X-Forwarded-For`, the other headers are the client's.
+        let spoofed = headers(&[
+            ("forwarded", "for=6.6.6.6;proto=https;host=evil.example"),
+            ("x-real-ip", "6.6.6.6"),
+            ("x-forwarded-for", "1.1.1.1"),
+            ("host", "example.com"),
+        ]);
+        let (client, origin) = resolve(
+            ip("10.0.0.1"),
+            &spoofed,
+            &proxies(),
+            ForwardedHeader::XForwardedFor,
+        );
+        assert_eq!(*client.ip(), ip("1.1.1.1"));
+        assert_eq!(origin.scheme(), &Scheme::HTTP);
+        assert_eq!(origin.host(), Some("example.com"));
+
+        // Nothing falls back to another header when the configured one is missing.
+        for header in [ForwardedHeader::Forwarded, ForwardedHeader::XRealIp] {
+            let forwarded_for = headers(&[("x-forwarded-for", "6.6.6.6")]);
+            let (client, _) = resolve(ip("10.0.0.1"), &forwarded_for, &proxies(), header);
+            assert_eq!(*client.ip(), ip("10.0.0.1"), "{header:?}");
+        }
+    }
+
+    #[test]
+    fn test_all_trusted_yields_leftmost() {
+        let headers = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+        let (client, _) = resolve(
+            ip("10.0.0.1"),
+            &headers,
+            &proxies(),
+            ForwardedHeader::XForwardedFor,
+        );
+
+        assert_eq!(*client.ip(), ip("10.0.0.3"));
+    }
+
+    #[test]
+    fn test_mapped_ipv4_is_canonicalized() {
+        let headers = headers(&[("x-forwarded-for", "::ffff:1.1.1.1")]);
+        let peer = IpAddr::V6(Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped());
+        let (client, _) = resolve(peer, &headers, &proxies(), ForwardedHeader::XForwardedFor);
+
+        assert_eq!(*client.ip(), ip("1.1.1.1"));
+    }
+}
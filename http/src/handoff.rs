@@ -0,0 +1,538 @@
+//! Restarts without downtime by passing the listening sockets to a new process.
+//!
+//! The running process hands its listeners to the new one, either by re-executing the binary
+//! with the sockets inherited ([`Handoff::restart`]) or by sending them over a Unix socket to a
+//! process started separately ([`Handoff::serve`]). Both processes accept on the same sockets, so
+//! no connection is refused. Once the new process reports it is ready, with
+//! [`ListenFds::complete_handoff`](crate::listener::ListenFds::complete_handoff), the handoff
+//! resolves and the old process should drain and exit, for example by passing it as the shutdown
+//! future of [`Server::serve_with_shutdown`](crate::server::Server::serve_with_shutdown).
+//!
+//! When running under systemd, the service needs `NotifyAccess=all` so the new process can take
+//! over as main process. The old process then skips
+//! [`notify::stopping`](crate::notify::stopping) while it drains, which would stop the service.
+use std::{
+    io::{self, Write},
+    mem,
+    os::{
+        fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{fs::PermissionsExt, net::UnixStream as StdUnixStream},
+    },
+    path::Path,
+    ptr,
+    time::Duration,
+};
+
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, Interest},
+    net::{UnixListener, UnixStream},
+    process::Command,
+    time,
+};
+
+use crate::{
+    listener::{self, SocketListener},
+    notify,
+};
+
+/// The listeners inherited from the previous process, as `name=fd` pairs separated by commas.
+const HANDOFF_FDS: &str = "CORTEV_HANDOFF_FDS";
+
+/// The socket to report readiness to the previous process.
+const HANDOFF_READY: &str = "CORTEV_HANDOFF_READY";
+
+/// The byte sent by the new process once it is ready.
+const READY: u8 = b'1';
+
+/// The most listeners passed in a single handoff.
+const MAX_FDS: usize = 32;
+
+const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The listeners of the running process, to pass to its replacement.
+///
+/// ```no_run
+/// # use cortev_http::{handoff::Handoff, listener::SocketListener, server::Server};
+/// # use tokio::signal::unix::{signal, SignalKind};
+/// # async fn run(listener: SocketListener, router: axum::Router) -> std::io::Result<()> {
+/// let handoff = Handoff::new().with_listener("http", &listener)?;
+/// let restarted = async move {
+///     let mut restart = signal(SignalKind::user_defined2()).unwrap();
+///     while restart.recv().await.is_some() {
+///         if handoff.restart().await.is_ok() {
+///             break;
+///         }
+///     }
+/// };
+///
+/// Server::new()
+///     .serve_with_shutdown(listener, router, restarted)
+///     .await
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Handoff {
+    listeners: Vec<(String, OwnedFd)>,
+    ready_timeout: Duration,
+}
+
+impl Handoff {
+    pub fn new() -> Self {
+        Self {
+            listeners: vec![],
+            ready_timeout: DEFAULT_READY_TIMEOUT,
+        }
+    }
+
+    /// Sets how long the new process may take to become ready, 60 seconds by default. A process
+    /// still starting afterwards is given up on.
+    #[must_use]
+    pub fn with_ready_timeout(mut self, timeout: Duration) -> Self {
+        self.ready_timeout = timeout;
+        self
+    }
+
+    /// Adds `listener`, which the new process takes with
+    /// [`ListenFds::take`](crate::listener::ListenFds::take) and the name `name`.
+    ///
+    /// # Errors
+    /// Returns an error if the socket cannot be duplicated.
+    pub fn with_listener(mut self, name: &str, listener: &SocketListener) -> io::Result<Self> {
+        self.listeners
+            .push((name.to_owned(), listener.as_fd().try_clone_to_owned()?));
+        Ok(self)
+    }
+
+    /// Re-executes the current binary with the same arguments and the listeners inherited, then
+    /// waits until it is ready.
+    ///
+    /// # Errors
+    /// Returns `Error::NotReady` if the new process exits, gives up or times out before it is
+    /// ready.
+    pub async fn restart(&self) -> Result<(), Error> {
+        let mut command = Command::new(std::env::current_exe()?);
+        command.args(std::env::args_os().skip(1));
+        self.spawn(command).await
+    }
+
+    async fn spawn(&self, mut command: Command) -> Result<(), Error> {
+        let (ready, ready_child) = StdUnixStream::pair()?;
+
+        let inherited: Vec<RawFd> = self
+            .listeners
+            .iter()
+            .map(|(_, fd)| fd.as_raw_fd())
+            .chain([ready_child.as_raw_fd()])
+            .collect();
+        let fds = self
+            .listeners
+            .iter()
+            .map(|(name, fd)| format!("{name}={}", fd.as_raw_fd()))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        command
+            .env(HANDOFF_FDS, fds)
+            .env(HANDOFF_READY, ready_child.as_raw_fd().to_string());
+
+        // Safety: `fcntl` is async-signal-safe and the closure does not allocate.
+        unsafe {
+            command.pre_exec(move || inherited.iter().try_for_each(|fd| set_inheritable(*fd)));
+        }
+
+        // Once ready, the child is reaped by the runtime when it exits, this process does not
+        // wait for it.
+        let mut child = command.spawn()?;
+        drop(ready_child);
+
+        let ready = async {
+            ready.set_nonblocking(true)?;
+            self.wait_ready(UnixStream::from_std(ready)?).await
+        };
+        if let Err(error) = ready.await {
+            // A process which did not take over must not keep accepting next to this one.
+            let _ = child.kill().await;
+            return Err(error);
+        }
+        notify::set_handed_off();
+        Ok(())
+    }
+
+    /// Sends the listeners to the first process connecting to the Unix socket at `path` that
+    /// becomes ready, see [`ListenFds::receive`](crate::listener::ListenFds::receive).
+    ///
+    /// A stale socket left at `path` is replaced. The socket is only accessible to the owner,
+    /// and only processes of the same user are handed the listeners. Processes failing or timing
+    /// out before they are ready are ignored, and the listeners are offered to the next one.
+    ///
+    /// # Errors
+    /// Returns an error if `path` cannot be bound.
+    pub async fn serve<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        listener::remove_stale_socket(path)?;
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+
+        loop {
+            if self.hand_to(&listener).await.is_ok() {
+                notify::set_handed_off();
+                return Ok(());
+            }
+        }
+    }
+
+    async fn hand_to(&self, listener: &UnixListener) -> Result<(), Error> {
+        let (stream, _) = listener.accept().await?;
+
+        // The socket may have been reached before its permissions were restricted.
+        // Safety: `geteuid` has no preconditions and cannot fail.
+        if stream.peer_cred()?.uid() != unsafe { libc::geteuid() } {
+            return Err(io::Error::from(io::ErrorKind::PermissionDenied).into());
+        }
+
+        let names = self
+            .listeners
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let fds: Vec<RawFd> = self
+            .listeners
+            .iter()
+            .map(|(_, fd)| fd.as_raw_fd())
+            .collect();
+
+        stream
+            .async_io(Interest::WRITABLE, || {
+                send_fds(stream.as_raw_fd(), names.as_bytes(), &fds)
+            })
+            .await?;
+
+        self.wait_ready(stream).await
+    }
+
+    async fn wait_ready(&self, mut stream: UnixStream) -> Result<(), Error> {
+        let mut ready = [0];
+        match time::timeout(self.ready_timeout, stream.read(&mut ready)).await {
+            Ok(Ok(1)) if ready[0] == READY => Ok(()),
+            Ok(Err(error)) => Err(error.into()),
+            _ => Err(Error::NotReady),
+        }
+    }
+}
+
+impl Default for Handoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Takes the listeners inherited from the previous process, if any.
+pub(crate) fn inherited() -> Result<Option<(Vec<(String, OwnedFd)>, StdUnixStream)>, Error> {
+    let (Ok(fds), Ok(ready)) = (std::env::var(HANDOFF_FDS), std::env::var(HANDOFF_READY)) else {
+        return Ok(None);
+    };
+    std::env::remove_var(HANDOFF_FDS);
+    std::env::remove_var(HANDOFF_READY);
+
+    adopt_inherited(&fds, &ready).map(Some)
+}
+
+fn adopt_inherited(
+    fds: &str,
+    ready: &str,
+) -> Result<(Vec<(String, OwnedFd)>, StdUnixStream), Error> {
+    let ready = adopt(parse_fd(ready)?)?;
+    let listeners = fds
+        .split(',')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, fd) = entry.split_once('=').ok_or(Error::InvalidMessage)?;
+            Ok((name.to_owned(), adopt(parse_fd(fd)?)?))
+        })
+        .collect::<Result<_, Error>>()?;
+
+    Ok((listeners, StdUnixStream::from(ready)))
+}
+
+/// Receives the listeners from the process serving the handoff socket at `path`.
+pub(crate) fn receive(path: &Path) -> Result<(Vec<(String, OwnedFd)>, StdUnixStream), Error> {
+    let stream = StdUnixStream::connect(path)?;
+
+    let mut names = [0; 4096];
+    let (len, fds) = recv_fds(stream.as_raw_fd(), &mut names)?;
+    let names = std::str::from_utf8(&names[..len]).map_err(|_| Error::InvalidMessage)?;
+
+    let names: Vec<_> = names.split('\n').filter(|name| !name.is_empty()).collect();
+    if names.len() != fds.len() {
+        return Err(Error::InvalidMessage);
+    }
+    for fd in &fds {
+        listener::set_cloexec(fd)?;
+    }
+
+    let listeners = names.into_iter().map(str::to_owned).zip(fds).collect();
+    Ok((listeners, stream))
+}
+
+/// Tells the previous process this one is ready, and takes over as main process of the service.
+pub(crate) fn complete(mut ready: StdUnixStream) -> io::Result<()> {
+    notify::notify(&format!("MAINPID={}", std::process::id()))?;
+    ready.write_all(&[READY])
+}
+
+fn parse_fd(value: &str) -> Result<RawFd, Error> {
+    value
+        .parse()
+        .ok()
+        .filter(|fd| *fd >= 0)
+        .ok_or(Error::InvalidMessage)
+}
+
+fn adopt(raw_fd: RawFd) -> io::Result<OwnedFd> {
+    // Safety: the previous process passed the file descriptor to this one, and the variables
+    // naming it are unset so it is only adopted once.
+    let fd = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+    listener::set_cloexec(&fd)?;
+    Ok(fd)
+}
+
+fn set_inheritable(fd: RawFd) -> io::Result<()> {
+    // Safety: `fd` is owned by the `Handoff` for the duration of the spawn.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A control buffer large enough for `MAX_FDS` descriptors, aligned for `cmsghdr`.
+fn control_buffer() -> (Vec<u64>, usize) {
+    // Safety: `CMSG_SPACE` only computes a size.
+    let space = unsafe { libc::CMSG_SPACE((MAX_FDS * mem::size_of::<RawFd>()) as u32) } as usize;
+    (vec![0; space.div_ceil(mem::size_of::<u64>())], space)
+}
+
+fn send_fds(socket: RawFd, payload: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    if fds.len() > MAX_FDS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too many listeners to hand off",
+        ));
+    }
+
+    let (mut control, space) = control_buffer();
+    let mut iov = libc::iovec {
+        iov_base: payload.as_ptr().cast_mut().cast(),
+        iov_len: payload.len(),
+    };
+
+    // Safety: the message points to `iov` and `control`, which outlive the call, and the control
+    // buffer has room for a header and `fds`.
+    let sent = unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = space as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of_val(fds) as u32) as _;
+        ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg).cast(), fds.len());
+        msg.msg_controllen = (*cmsg).cmsg_len as _;
+
+        libc::sendmsg(socket, &msg, 0)
+    };
+
+    match sent {
+        sent if sent < 0 => Err(io::Error::last_os_error()),
+        sent if sent as usize != payload.len() => Err(io::ErrorKind::WriteZero.into()),
+        _ => Ok(()),
+    }
+}
+
+fn recv_fds(socket: RawFd, buf: &mut [u8]) -> Result<(usize, Vec<OwnedFd>), Error> {
+    let (mut control, space) = control_buffer();
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+
+    // Safety: the message points to `iov` and `control`, which outlive the call.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = space as _;
+
+    // Safety: see above.
+    let received = unsafe { libc::recvmsg(socket, &mut msg, 0) };
+    if received < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let mut fds = vec![];
+    // Safety: the headers are read within the `msg_controllen` bytes filled by `recvmsg`, and
+    // each descriptor of an `SCM_RIGHTS` message is new and owned by this process.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                let len = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize)
+                    / mem::size_of::<RawFd>();
+                for index in 0..len {
+                    fds.push(OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(index))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(Error::InvalidMessage);
+    }
+    Ok((received as usize, fds))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to hand off the listeners")]
+    Io(#[from] io::Error),
+
+    #[error("the new process was not ready")]
+    NotReady,
+
+    #[error("the handoff message is invalid")]
+    InvalidMessage,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::IntoRawFd;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serve_and_receive() {
+        let path = std::env::temp_dir().join(format!("handoff-{}.sock", std::process::id()));
+        let listener =
+            SocketListener::from(tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let addr = listener.local_addr().unwrap();
+
+        let handoff = Handoff::new().with_listener("http", &listener).unwrap();
+        let served = tokio::spawn({
+            let path = path.clone();
+            async move { handoff.serve(&path).await }
+        });
+
+        let (listeners, ready) = loop {
+            match tokio::task::spawn_blocking({
+                let path = path.clone();
+                move || receive(&path)
+            })
+            .await
+            .unwrap()
+            {
+                Ok(received) => break received,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+
+        assert!(!served.is_finished());
+        let (name, fd) = listeners.into_iter().next().unwrap();
+        assert_eq!(name, "http");
+        assert_eq!(
+            SocketListener::from_fd(fd).unwrap().local_addr().unwrap(),
+            addr
+        );
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        complete(ready).unwrap();
+        served.await.unwrap().unwrap();
+        assert!(notify::handed_off());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restart() {
+        let listener =
+            SocketListener::from(tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let handoff = Handoff::new()
+            .with_listener("http", &listener)
+            .unwrap()
+            .with_ready_timeout(Duration::from_millis(500));
+
+        let mut command = Command::new(std::env::current_exe().unwrap());
+        command
+            .args(["handoff::tests::test_restarted", "--exact", "--ignored"])
+            .stdout(std::process::Stdio::null());
+        handoff.spawn(command).await.unwrap();
+        assert!(notify::handed_off());
+
+        // A process exiting or hanging before it is ready keeps this one in charge.
+        assert!(matches!(
+            handoff.spawn(Command::new("true")).await,
+            Err(Error::NotReady)
+        ));
+        let pid_file = std::env::temp_dir().join(format!("handoff-{}.pid", std::process::id()));
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("echo $$ > {}; exec sleep 5", pid_file.display()));
+        assert!(matches!(handoff.spawn(command).await, Err(Error::NotReady)));
+
+        // The process given up on is killed and reaped.
+        let pid: libc::pid_t = std::fs::read_to_string(&pid_file)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        // Safety: signal 0 only checks whether the process exists.
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+        std::fs::remove_file(&pid_file).unwrap();
+    }
+
+    /// Run by `test_restart` as the new process.
+    #[test]
+    #[ignore]
+    fn test_restarted() {
+        let Some((listeners, ready)) = inherited().unwrap() else {
+            return;
+        };
+        let (name, fd) = listeners.into_iter().next().unwrap();
+        assert_eq!(name, "http");
+        assert!(std::net::TcpListener::from(fd).local_addr().is_ok());
+        complete(ready).unwrap();
+    }
+
+    #[test]
+    fn test_inherited() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let (ready, ready_child) = StdUnixStream::pair().unwrap();
+        let fds = format!("http={}", OwnedFd::from(tcp).into_raw_fd());
+        let ready_fd = OwnedFd::from(ready_child).into_raw_fd().to_string();
+
+        let (listeners, ready_child) = adopt_inherited(&fds, &ready_fd).unwrap();
+        let (name, fd) = listeners.into_iter().next().unwrap();
+        assert_eq!(name, "http");
+        let tcp = std::net::TcpListener::from(fd);
+        assert_eq!(tcp.local_addr().unwrap(), addr);
+
+        complete(ready_child).unwrap();
+        let mut byte = [0];
+        io::Read::read_exact(&mut &ready, &mut byte).unwrap();
+        assert_eq!(byte[0], READY);
+
+        assert!(matches!(
+            adopt_inherited("http", "-1"),
+            Err(Error::InvalidMessage)
+        ));
+    }
+}
@@ -1,9 +1,27 @@
-use std::net::IpAddr;
+use std::{
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, PoisonError, RwLock},
+};
 
-use axum::{extract::connect_info::Connected, serve::IncomingStream};
+use axum::{
+    extract::{connect_info::Connected, ConnectInfo},
+    serve::IncomingStream,
+};
+use http::Extensions;
 use ipnet::IpNet;
+use thiserror::Error;
 
-#[derive(Debug, Clone)]
+/// The networks of the proxies whose forwarding headers are honoured.
+///
+/// ```no_run
+/// # use cortev_http::ip::TrustedProxies;
+/// let proxies = TrustedProxies::new()
+///     .trust_private()
+///     .trust_cidrs("203.0.113.0/24, 198.51.100.7")?;
+/// # Ok::<_, cortev_http::ip::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct TrustedProxies {
     proxies: Vec<IpNet>,
 }
@@ -13,25 +31,270 @@ impl TrustedProxies {
         Self { proxies: vec![] }
     }
 
+    /// Trusts the proxies in `network`.
+    #[must_use]
+    pub fn trust(mut self, network: IpNet) -> Self {
+        self.proxies.push(network);
+        self
+    }
+
+    /// Trusts every network of a comma separated list of CIDRs or addresses.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidNetwork` if an entry is neither a CIDR nor an address.
+    pub fn trust_cidrs(self, cidrs: &str) -> Result<Self, Error> {
+        parse_networks(cidrs).try_fold(self, |proxies, network| Ok(proxies.trust(network?)))
+    }
+
+    /// Trusts every address, for applications only reachable through proxies.
+    #[must_use]
+    pub fn trust_all(self) -> Self {
+        self.trust_networks(&["0.0.0.0/0", "::/0"])
+    }
+
+    /// Trusts the loopback, private and link-local ranges.
+    #[must_use]
+    pub fn trust_private(self) -> Self {
+        self.trust_networks(&[
+            "127.0.0.0/8",
+            "10.0.0.0/8",
+            "172.16.0.0/12",
+            "192.168.0.0/16",
+            "169.254.0.0/16",
+            "::1/128",
+            "fc00::/7",
+            "fe80::/10",
+        ])
+    }
+
+    /// Trusts the Cloudflare edge ranges bundled with this crate.
+    #[cfg(feature = "bundled-ranges")]
+    #[must_use]
+    pub fn trust_cloudflare(self) -> Self {
+        self.trust_list(include_str!("../ranges/cloudflare.txt"))
+            .expect("the bundled Cloudflare ranges are valid")
+    }
+
+    /// Trusts the VPC-internal ranges: the RFC 1918 private ranges and the `100.64.0.0/10` shared
+    /// address space.
+    ///
+    /// Load balancers inside a VPC, such as AWS Elastic Load Balancing, connect from these ranges,
+    /// but so does anything else in the VPC, this does not check that a peer is a load balancer.
+    /// Prefer trusting the CIDR of the load balancer subnets when it is known.
+    #[must_use]
+    pub fn trust_vpc(self) -> Self {
+        self.trust_networks(&[
+            "10.0.0.0/8",
+            "172.16.0.0/12",
+            "192.168.0.0/16",
+            "100.64.0.0/10",
+        ])
+    }
+
+    /// Trusts the networks listed in the environment variable `name`, as a comma separated list.
+    ///
+    /// A missing variable trusts nothing more, and `*` trusts every address.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidNetwork` if an entry is neither a CIDR nor an address.
+    pub fn from_env(name: &str) -> Result<Self, Error> {
+        match std::env::var(name) {
+            Ok(value) if value.trim() == "*" => Ok(Self::new().trust_all()),
+            Ok(value) => Self::new().trust_cidrs(&value),
+            Err(_) => Ok(Self::new()),
+        }
+    }
+
+    /// Trusts the networks listed in the file at `path`, one per line.
+    ///
+    /// Blank lines and lines starting with `#` are ignored.
+    ///
+    /// # Errors
+    /// Returns `Error::Io` if the file cannot be read and `Error::InvalidNetwork` if a line is
+    /// neither a CIDR nor an address.
+    pub fn load_file<P: AsRef<Path>>(self, path: P) -> Result<Self, Error> {
+        let content = std::fs::read_to_string(path)?;
+        self.trust_list(&content)
+    }
+
     pub fn is_trusted(&self, ip: &IpAddr) -> bool {
         self.proxies.iter().any(|proxy| proxy.contains(ip))
     }
+
+    pub fn networks(&self) -> &[IpNet] {
+        &self.proxies
+    }
+
+    fn trust_networks(self, networks: &[&str]) -> Self {
+        networks.iter().fold(self, |proxies, network| {
+            proxies.trust(network.parse().expect("the preset network is valid"))
+        })
+    }
+
+    fn trust_list(self, content: &str) -> Result<Self, Error> {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .try_fold(
+                self,
+                |proxies, line| Ok(proxies.trust(parse_network(line)?)),
+            )
+    }
+}
+
+/// Parses a comma separated list of CIDRs or addresses.
+fn parse_networks(cidrs: &str) -> impl Iterator<Item = Result<IpNet, Error>> + '_ {
+    cidrs
+        .split(',')
+        .map(str::trim)
+        .filter(|cidr| !cidr.is_empty())
+        .map(parse_network)
+}
+
+/// Parses a CIDR, or a single address as a network of one host.
+fn parse_network(value: &str) -> Result<IpNet, Error> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| Error::InvalidNetwork(value.to_owned()))
+}
+
+/// Networks allowed or denied access, checked against the client IP by the
+/// [`IpFilterLayer`](crate::middleware::layer::IpFilterLayer).
+///
+/// Denied networks take precedence, and once a network is allowed every address outside the
+/// allowed networks is denied.
+///
+/// ```
+/// # use cortev_http::ip::IpFilter;
+/// let office = IpFilter::new()
+///     .allow_cidrs("10.8.0.0/16, 192.0.2.10")?
+///     .deny_cidrs("10.8.255.0/24")?;
+/// assert!(office.is_allowed(&"10.8.1.2".parse().unwrap()));
+/// assert!(!office.is_allowed(&"10.8.255.2".parse().unwrap()));
+/// # Ok::<_, cortev_http::ip::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpFilter {
+    allowed: Vec<IpNet>,
+    denied: Vec<IpNet>,
+}
+
+impl IpFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn allow(mut self, network: IpNet) -> Self {
+        self.allowed.push(network);
+        self
+    }
+
+    #[must_use]
+    pub fn deny(mut self, network: IpNet) -> Self {
+        self.denied.push(network);
+        self
+    }
+
+    /// Allows every network of a comma separated list of CIDRs or addresses.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidNetwork` if an entry is neither a CIDR nor an address.
+    pub fn allow_cidrs(self, cidrs: &str) -> Result<Self, Error> {
+        parse_networks(cidrs).try_fold(self, |filter, network| Ok(filter.allow(network?)))
+    }
+
+    /// Denies every network of a comma separated list of CIDRs or addresses.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidNetwork` if an entry is neither a CIDR nor an address.
+    pub fn deny_cidrs(self, cidrs: &str) -> Result<Self, Error> {
+        parse_networks(cidrs).try_fold(self, |filter, network| Ok(filter.deny(network?)))
+    }
+
+    pub fn is_allowed(&self, ip: &IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if self.denied.iter().any(|network| network.contains(&ip)) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(|network| network.contains(&ip))
+    }
+}
+
+/// A shared, reloadable set of trusted proxies.
+///
+/// Clones share the same set, so the handle given to the
+/// [`TrustedProxyLayer`](crate::middleware::layer::TrustedProxyLayer) can be kept to refresh the
+/// list, for example on `SIGHUP`, without restarting the server.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxiesHandle {
+    proxies: Arc<RwLock<Arc<TrustedProxies>>>,
+}
+
+impl TrustedProxiesHandle {
+    pub fn new(proxies: TrustedProxies) -> Self {
+        Self {
+            proxies: Arc::new(RwLock::new(Arc::new(proxies))),
+        }
+    }
+
+    /// Returns the current set of trusted proxies.
+    pub fn current(&self) -> Arc<TrustedProxies> {
+        self.proxies
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replaces the trusted proxies, requests in flight keep the previous set.
+    pub fn reload(&self, proxies: TrustedProxies) {
+        *self.proxies.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(proxies);
+    }
+
+    /// Replaces the trusted proxies with the result of `load`, keeping the current set if it
+    /// fails.
+    ///
+    /// # Errors
+    /// Returns the error of `load`.
+    pub fn reload_with<F>(&self, load: F) -> Result<(), Error>
+    where
+        F: FnOnce() -> Result<TrustedProxies, Error>,
+    {
+        self.reload(load()?);
+        Ok(())
+    }
 }
 
-impl Default for TrustedProxies {
-    fn default() -> Self {
-        Self::new()
+impl From<Arc<TrustedProxies>> for TrustedProxiesHandle {
+    fn from(proxies: Arc<TrustedProxies>) -> Self {
+        Self {
+            proxies: Arc::new(RwLock::new(proxies)),
+        }
     }
 }
 
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0:?} is not a valid network or address")]
+    InvalidNetwork(String),
+
+    #[error("failed to read the trusted proxies")]
+    Io(#[from] std::io::Error),
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ClientInfo {
     ip: IpAddr,
 }
 
 impl ClientInfo {
+    /// Creates the info of a peer, mapping IPv4-mapped IPv6 addresses back to IPv4.
     pub fn new(ip: IpAddr) -> Self {
-        Self { ip }
+        Self {
+            ip: ip.to_canonical(),
+        }
     }
 
     pub fn ip(&self) -> &IpAddr {
@@ -39,16 +302,44 @@ impl ClientInfo {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// The address of the client, resolved through the trusted proxies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ClientIp {
     ip: IpAddr,
 }
 
+impl ClientIp {
+    /// Creates a client address, mapping IPv4-mapped IPv6 addresses back to IPv4.
+    pub fn new(ip: IpAddr) -> Self {
+        Self {
+            ip: ip.to_canonical(),
+        }
+    }
+
+    pub fn ip(&self) -> &IpAddr {
+        &self.ip
+    }
+}
+
+/// Returns the resolved client address of a request, falling back to the peer address when the
+/// `TrustedProxyLayer` is not installed.
+pub(crate) fn client_ip(extensions: &Extensions) -> Option<IpAddr> {
+    if let Some(client_ip) = extensions.get::<ClientIp>() {
+        return Some(*client_ip.ip());
+    }
+    extensions
+        .get::<ClientInfo>()
+        .or_else(|| {
+            extensions
+                .get::<ConnectInfo<ClientInfo>>()
+                .map(|info| &info.0)
+        })
+        .map(|info| *info.ip())
+}
+
 impl Connected<IncomingStream<'_>> for ClientInfo {
     fn connect_info(stream: IncomingStream<'_>) -> Self {
-        ClientInfo {
-            ip: stream.remote_addr().ip().to_canonical(),
-        }
+        ClientInfo::new(stream.remote_addr().ip())
     }
 }
 
@@ -57,8 +348,101 @@ mod tests {
     use super::*;
     use std::net::{IpAddr, Ipv4Addr};
 
+    #[test]
+    fn test_trust_cidrs() {
+        let proxies = TrustedProxies::new()
+            .trust_cidrs("10.0.0.0/8, 192.0.2.1,2001:db8::/32")
+            .unwrap();
+
+        assert!(proxies.is_trusted(&"10.1.2.3".parse().unwrap()));
+        assert!(proxies.is_trusted(&"192.0.2.1".parse().unwrap()));
+        assert!(!proxies.is_trusted(&"192.0.2.2".parse().unwrap()));
+        assert!(proxies.is_trusted(&"2001:db8::1".parse().unwrap()));
+        assert!(matches!(
+            TrustedProxies::new().trust_cidrs("10.0.0.0/8, nope"),
+            Err(Error::InvalidNetwork(value)) if value == "nope"
+        ));
+    }
+
+    #[test]
+    fn test_presets() {
+        let private = TrustedProxies::new().trust_private();
+        assert!(private.is_trusted(&"127.0.0.1".parse().unwrap()));
+        assert!(private.is_trusted(&"fd00::1".parse().unwrap()));
+        assert!(!private.is_trusted(&"1.1.1.1".parse().unwrap()));
+
+        let vpc = TrustedProxies::new().trust_vpc();
+        assert!(vpc.is_trusted(&"10.0.1.1".parse().unwrap()));
+        assert!(vpc.is_trusted(&"100.64.0.1".parse().unwrap()));
+        assert!(!vpc.is_trusted(&"127.0.0.1".parse().unwrap()));
+
+        let all = TrustedProxies::new().trust_all();
+        assert!(all.is_trusted(&"1.1.1.1".parse().unwrap()));
+        assert!(all.is_trusted(&"2606:4700::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ip_filter() {
+        let open = IpFilter::new();
+        assert!(open.is_allowed(&"1.1.1.1".parse().unwrap()));
+
+        let denylist = IpFilter::new().deny_cidrs("203.0.113.0/24").unwrap();
+        assert!(denylist.is_allowed(&"1.1.1.1".parse().unwrap()));
+        assert!(!denylist.is_allowed(&"203.0.113.9".parse().unwrap()));
+        assert!(!denylist.is_allowed(&"::ffff:203.0.113.9".parse().unwrap()));
+
+        let allowlist = IpFilter::new()
+            .allow_cidrs("10.0.0.0/8, 2001:db8::/32")
+            .unwrap()
+            .deny_cidrs("10.0.0.13")
+            .unwrap();
+        assert!(allowlist.is_allowed(&"10.1.2.3".parse().unwrap()));
+        assert!(allowlist.is_allowed(&"2001:db8::1".parse().unwrap()));
+        assert!(!allowlist.is_allowed(&"10.0.0.13".parse().unwrap()));
+        assert!(!allowlist.is_allowed(&"192.168.1.1".parse().unwrap()));
+
+        assert!(matches!(
+            IpFilter::new().allow_cidrs("10.0.0.0/33"),
+            Err(Error::InvalidNetwork(value)) if value == "10.0.0.0/33"
+        ));
+    }
+
+    #[test]
+    fn test_bundled_lists_parse() {
+        let cloudflare = TrustedProxies::new()
+            .trust_list(include_str!("../ranges/cloudflare.txt"))
+            .unwrap();
+        assert!(cloudflare.is_trusted(&"173.245.48.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_load_file_and_reload() {
+        let path = std::env::temp_dir().join(format!("trusted-proxies-{}", std::process::id()));
+        std::fs::write(&path, "# proxies\n\n10.0.0.0/8\n").unwrap();
+
+        let handle = TrustedProxiesHandle::new(TrustedProxies::new());
+        let shared = handle.clone();
+        assert!(!shared.current().is_trusted(&"10.0.0.1".parse().unwrap()));
+
+        handle
+            .reload_with(|| TrustedProxies::new().load_file(&path))
+            .unwrap();
+        assert!(shared.current().is_trusted(&"10.0.0.1".parse().unwrap()));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(handle
+            .reload_with(|| TrustedProxies::new().load_file(&path))
+            .is_err());
+        assert!(shared.current().is_trusted(&"10.0.0.1".parse().unwrap()));
+    }
+
     #[test]
     fn test_ip() {
         let ipv4 = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let mapped = IpAddr::V6(Ipv4Addr::new(127, 0, 0, 1).to_ipv6_mapped());
+
+        assert_eq!(*ClientIp::new(mapped).ip(), ipv4);
+        assert_eq!(*ClientInfo::new(mapped).ip(), ipv4);
+        assert_eq!(*ClientIp::new(ipv4).ip(), ipv4);
     }
 }
@@ -0,0 +1,12 @@
+pub mod extract;
+pub mod forwarded;
+pub mod handoff;
+pub mod ip;
+pub mod listener;
+pub mod middleware;
+pub mod notify;
+pub mod proxy_protocol;
+pub mod rate_limit;
+pub mod server;
+#[cfg(feature = "tls")]
+pub mod tls;
@@ -1,50 +1,441 @@
-use std::{ops::Deref, os::fd::FromRawFd};
+use std::{
+    fmt, io,
+    net::SocketAddr,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::fs::FileTypeExt,
+    },
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+};
 
+use socket2::{Socket, Type};
 use thiserror::Error;
-use tokio::net::{TcpListener, ToSocketAddrs};
+
+use crate::handoff;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+};
+
+/// The first file descriptor passed by systemd, see
+/// [`sd_listen_fds(3)`](https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html).
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// The name systemd gives to sockets without a `FileDescriptorName`.
+const UNKNOWN_NAME: &str = "unknown";
+
+/// The sockets passed to this process by systemd socket activation.
+///
+/// Sockets are named after the `FileDescriptorName` of their socket unit, so a service can
+/// receive several, such as `http` and `admin`, and pick each by name.
+///
+/// ```no_run
+/// # use cortev_http::listener::{ListenFds, SocketListener};
+/// # fn main() -> Result<(), cortev_http::listener::Error> {
+/// let mut fds = ListenFds::from_env()?;
+/// let runtime = tokio::runtime::Runtime::new()?;
+/// runtime.block_on(async {
+///     let http = SocketListener::named(&mut fds, "http", &"127.0.0.1:8080".into()).await?;
+///     let admin =
+///         SocketListener::named(&mut fds, "admin", &"unix:/run/app/admin.sock".into()).await?;
+///     # Ok(())
+/// })
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct ListenFds {
+    fds: Vec<(String, OwnedFd)>,
+    handoff: Option<std::os::unix::net::UnixStream>,
+}
+
+impl ListenFds {
+    /// Takes the sockets passed to this process, by systemd or by the previous process of a
+    /// [`Handoff::restart`](crate::handoff::Handoff::restart).
+    ///
+    /// The systemd sockets are only taken when `LISTEN_PID` names this process. The variables
+    /// naming the sockets are unset so child processes do not adopt them too, and the sockets are
+    /// marked close-on-exec.
+    ///
+    /// Unsetting environment variables is only sound while no other thread reads them, so call
+    /// this before starting threads, in particular before building a multi-threaded Tokio
+    /// runtime.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidListenFds` if `LISTEN_FDS` or `LISTEN_PID` is not a number, and
+    /// `Error::Handoff` if the inherited sockets are invalid.
+    pub fn from_env() -> Result<Self, Error> {
+        let mut listen_fds = Self::from_systemd()?;
+
+        if let Some((fds, handoff)) = handoff::inherited()? {
+            listen_fds.fds.extend(fds);
+            listen_fds.handoff = Some(handoff);
+        }
+
+        Ok(listen_fds)
+    }
+
+    /// Receives the sockets from the running process serving a
+    /// [`Handoff`](crate::handoff::Handoff) on the Unix socket at `path`.
+    ///
+    /// # Errors
+    /// Returns `Error::Handoff` if nothing serves `path` or the message is invalid.
+    pub fn receive<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let (fds, handoff) = handoff::receive(path.as_ref())?;
+        Ok(Self {
+            fds,
+            handoff: Some(handoff),
+        })
+    }
+
+    /// Tells the process which handed off the sockets that this one is ready, so it can drain
+    /// and exit. Does nothing when the sockets did not come from a handoff.
+    ///
+    /// Call it once the sockets are taken and the application is about to serve. Dropping the
+    /// `ListenFds` before then tells the previous process the handoff failed, so it keeps serving.
+    ///
+    /// # Errors
+    /// Returns `Error::Handoff` if the previous process cannot be notified.
+    pub fn complete_handoff(&mut self) -> Result<(), Error> {
+        if let Some(handoff) = self.handoff.take() {
+            handoff::complete(handoff).map_err(handoff::Error::from)?;
+        }
+        Ok(())
+    }
+
+    fn from_systemd() -> Result<Self, Error> {
+        let Ok(listen_fds) = std::env::var("LISTEN_FDS") else {
+            return Ok(Self::default());
+        };
+        let listen_pid = std::env::var("LISTEN_PID").ok();
+        let listen_fdnames = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+
+        Self::adopt_systemd(listen_pid.as_deref(), &listen_fds, &listen_fdnames)
+    }
+
+    fn adopt_systemd(
+        listen_pid: Option<&str>,
+        listen_fds: &str,
+        listen_fdnames: &str,
+    ) -> Result<Self, Error> {
+        // Without `LISTEN_PID`, the variables may have been inherited from another process, and
+        // the file descriptors they name are not sockets meant for this one.
+        let Some(pid) = listen_pid else {
+            return Ok(Self::default());
+        };
+        if pid.parse::<u32>()? != std::process::id() {
+            return Ok(Self::default());
+        }
+
+        let count: RawFd = listen_fds.parse()?;
+        let mut names = listen_fdnames.split(':');
+
+        let fds = (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+            .map(|raw_fd| {
+                // Safety: the file descriptor is valid and owned by this process because systemd
+                // guarantees it, and `LISTEN_FDS` is unset so it is only adopted once.
+                let fd = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+                set_cloexec(&fd)?;
+
+                let name = names
+                    .next()
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or(UNKNOWN_NAME);
+                Ok((name.to_owned(), fd))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self { fds, handoff: None })
+    }
+
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// Returns the names of the sockets not taken yet.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fds.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Takes the first socket named `name`.
+    ///
+    /// # Errors
+    /// Returns `Error::UnsupportedSocket` if the socket is neither a TCP nor a Unix stream socket.
+    pub fn take(&mut self, name: &str) -> Result<Option<SocketListener>, Error> {
+        self.fds
+            .iter()
+            .position(|(fd_name, _)| fd_name == name)
+            .map(|index| SocketListener::from_fd(self.fds.remove(index).1))
+            .transpose()
+    }
+
+    /// Takes the first socket, whatever its name.
+    ///
+    /// # Errors
+    /// Returns `Error::UnsupportedSocket` if the socket is neither a TCP nor a Unix stream socket.
+    pub fn take_first(&mut self) -> Result<Option<SocketListener>, Error> {
+        if self.fds.is_empty() {
+            return Ok(None);
+        }
+        SocketListener::from_fd(self.fds.remove(0).1).map(Some)
+    }
+}
+
+pub(crate) fn set_cloexec(fd: &OwnedFd) -> io::Result<()> {
+    // Safety: `fd` is a valid file descriptor for the duration of the calls.
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+    if flags < 0
+        || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Removes the Unix socket left at `path` by a previous run, so it can be bound again.
+pub(crate) fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// An address to bind when no socket was passed by systemd.
+///
+/// Parsed from a string, `unix:` followed by a path is a Unix socket, anything else a TCP
+/// address such as `127.0.0.1:8080`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddr {
+    type Err = std::convert::Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(value.into())
+    }
+}
+
+impl From<&str> for BindAddr {
+    fn from(value: &str) -> Self {
+        match value.strip_prefix("unix:") {
+            Some(path) => Self::Unix(path.into()),
+            None => Self::Tcp(value.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => f.write_str(addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A listening socket, TCP or Unix.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
 
 /// A listener that supports systemd socket activation and fallback local binding.
 #[derive(Debug)]
 pub struct SocketListener {
-    listener: TcpListener,
+    listener: Listener,
 }
 
 impl SocketListener {
-    pub async fn new<T>(bind_addr: T) -> Result<Self, Error>
-    where
-        T: ToSocketAddrs,
-    {
-        if let Ok(listen_fds) = std::env::var("LISTEN_FDS") {
-            let listen_fds: i32 = listen_fds.parse()?;
+    /// Takes the socket named `name` from `fds`, or binds `fallback`.
+    ///
+    /// # Errors
+    /// Returns an error if the passed socket is invalid or `fallback` cannot be bound.
+    pub async fn named(
+        fds: &mut ListenFds,
+        name: &str,
+        fallback: &BindAddr,
+    ) -> Result<Self, Error> {
+        match fds.take(name)? {
+            Some(listener) => Ok(listener),
+            None => Self::bind(fallback).await,
+        }
+    }
 
-            if listen_fds != 1 {
-                return Err(Error::UnexpectedListenFds(listen_fds as usize));
+    /// Binds `addr`, replacing a stale Unix socket left by a previous run.
+    ///
+    /// # Errors
+    /// Returns `Error::Bind` if `addr` cannot be bound.
+    pub async fn bind(addr: &BindAddr) -> Result<Self, Error> {
+        match addr {
+            BindAddr::Tcp(addr) => Ok(TcpListener::bind(addr.as_str()).await?.into()),
+            BindAddr::Unix(path) => {
+                remove_stale_socket(path)?;
+                Ok(UnixListener::bind(path)?.into())
             }
+        }
+    }
 
-            // Safety: the file descriptor is valid because systemd guarantees it.
-            let raw_fd = 3;
-            let std_listener = unsafe { std::net::TcpListener::from_raw_fd(raw_fd) };
-            std_listener.set_nonblocking(true)?;
-
-            let listener = TcpListener::from_std(std_listener)?;
+    /// Adopts a listening socket, TCP or Unix.
+    ///
+    /// # Errors
+    /// Returns `Error::UnsupportedSocket` if `fd` is neither a TCP nor a Unix stream socket.
+    pub fn from_fd(fd: OwnedFd) -> Result<Self, Error> {
+        let socket = Socket::from(fd);
+        if socket.r#type()? != Type::STREAM {
+            return Err(Error::UnsupportedSocket);
+        }
+        let is_unix = socket.local_addr()?.is_unix();
+        socket.set_nonblocking(true)?;
 
-            Ok(Self { listener })
+        let listener = if is_unix {
+            Listener::Unix(UnixListener::from_std(socket.into())?)
         } else {
-            let listener = TcpListener::bind(bind_addr).await?;
-            Ok(Self { listener })
+            Listener::Tcp(TcpListener::from_std(socket.into())?)
+        };
+
+        Ok(Self { listener })
+    }
+
+    /// Accepts a connection, with the address of the peer for TCP connections.
+    ///
+    /// # Errors
+    /// Returns the error of the underlying listener.
+    pub async fn accept(&self) -> io::Result<(SocketStream, Option<SocketAddr>)> {
+        match &self.listener {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((SocketStream::Tcp(stream), Some(peer)))
+            }
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((SocketStream::Unix(stream), None))
+            }
         }
     }
 
-    pub fn into_inner(self) -> TcpListener {
+    /// Returns the address the socket is bound to.
+    ///
+    /// # Errors
+    /// Returns the error of the underlying listener.
+    pub fn local_addr(&self) -> io::Result<BindAddr> {
+        match &self.listener {
+            Listener::Tcp(listener) => Ok(BindAddr::Tcp(listener.local_addr()?.to_string())),
+            Listener::Unix(listener) => Ok(BindAddr::Unix(
+                listener
+                    .local_addr()?
+                    .as_pathname()
+                    .map(Into::into)
+                    .unwrap_or_default(),
+            )),
+        }
+    }
+
+    pub fn into_inner(self) -> Listener {
         self.listener
     }
 }
 
-impl Deref for SocketListener {
-    type Target = TcpListener;
+impl AsFd for SocketListener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match &self.listener {
+            Listener::Tcp(listener) => listener.as_fd(),
+            Listener::Unix(listener) => listener.as_fd(),
+        }
+    }
+}
+
+impl From<TcpListener> for SocketListener {
+    fn from(listener: TcpListener) -> Self {
+        Self {
+            listener: Listener::Tcp(listener),
+        }
+    }
+}
+
+impl From<UnixListener> for SocketListener {
+    fn from(listener: UnixListener) -> Self {
+        Self {
+            listener: Listener::Unix(listener),
+        }
+    }
+}
+
+/// A connection accepted by a [`SocketListener`].
+#[derive(Debug)]
+pub enum SocketStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for SocketStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for SocketStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            Self::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Self::Tcp(stream) => stream.is_write_vectored(),
+            Self::Unix(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.listener
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
     }
 }
 
@@ -53,9 +444,102 @@ pub enum Error {
     #[error("the listen file descriptors are invalid")]
     InvalidListenFds(#[from] std::num::ParseIntError),
 
-    #[error("expected exactly one listen file descriptor")]
-    UnexpectedListenFds(usize),
+    #[error("the listen file descriptor is not a TCP or Unix stream socket")]
+    UnsupportedSocket,
 
     #[error("failed to bind to address")]
     Bind(#[from] std::io::Error),
+
+    #[error("failed to take over the listen sockets")]
+    Handoff(#[from] handoff::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_addr() {
+        assert_eq!(
+            BindAddr::from("127.0.0.1:8080"),
+            BindAddr::Tcp("127.0.0.1:8080".to_owned())
+        );
+        assert_eq!(
+            BindAddr::from("unix:/run/app.sock"),
+            BindAddr::Unix("/run/app.sock".into())
+        );
+        assert_eq!(
+            BindAddr::from("unix:/run/app.sock").to_string(),
+            "unix:/run/app.sock"
+        );
+    }
+
+    #[test]
+    fn test_listen_pid_is_required() {
+        assert!(ListenFds::adopt_systemd(None, "2", "").unwrap().is_empty());
+        assert!(ListenFds::adopt_systemd(Some("0"), "2", "")
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            ListenFds::adopt_systemd(Some("self"), "2", ""),
+            Err(Error::InvalidListenFds(_))
+        ));
+
+        let pid = std::process::id().to_string();
+        assert!(ListenFds::adopt_systemd(Some(&pid), "0", "")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_take_named() {
+        let socket = |addr| OwnedFd::from(std::net::TcpListener::bind(addr).unwrap());
+        let mut fds = ListenFds {
+            fds: vec![
+                ("admin".to_owned(), socket("127.0.0.1:0")),
+                ("http".to_owned(), socket("127.0.0.1:0")),
+            ],
+            handoff: None,
+        };
+
+        assert!(fds.take("http").unwrap().is_some());
+        assert!(fds.take("http").unwrap().is_none());
+        assert_eq!(fds.names().collect::<Vec<_>>(), ["admin"]);
+        assert!(fds.take_first().unwrap().is_some());
+        assert!(fds.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_from_fd() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let listener = SocketListener::from_fd(tcp.into()).unwrap();
+        assert!(
+            matches!(listener.into_inner(), Listener::Tcp(tcp) if tcp.local_addr().unwrap() == addr)
+        );
+
+        let path = std::env::temp_dir().join(format!("listener-{}.sock", std::process::id()));
+        let unix = SocketListener::bind(&BindAddr::Unix(path.clone()))
+            .await
+            .unwrap();
+        let fd = match unix.into_inner() {
+            Listener::Unix(unix) => OwnedFd::from(unix.into_std().unwrap()),
+            Listener::Tcp(_) => panic!("expected a Unix listener"),
+        };
+        let listener = SocketListener::from_fd(fd).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), BindAddr::Unix(path.clone()));
+
+        // Binding again replaces the stale socket.
+        drop(listener);
+        assert!(SocketListener::bind(&BindAddr::Unix(path.clone()))
+            .await
+            .is_ok());
+        std::fs::remove_file(&path).unwrap();
+
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(matches!(
+            SocketListener::from_fd(udp.into()),
+            Err(Error::UnsupportedSocket)
+        ));
+    }
 }
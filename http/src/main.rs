@@ -1,42 +1,153 @@
-use std::sync::Arc;
+use std::{io, sync::Arc, time::Duration};
 
 use axum::{
-    extract::Request,
     response::{IntoResponse, Response},
     routing, Router,
 };
-use ip::{ClientInfo, TrustedProxies};
-use listener::SocketListener;
-use middleware::layer::TrustedProxyLayer;
-use tokio::signal;
-
-pub mod ip;
-pub mod listener;
-pub mod middleware;
-
-async fn handler(_request: Request) -> Response {
-    let ip = "bob";
-    (format!("Hello, {}!", ip)).into_response()
+use cortev_http::{
+    forwarded::ForwardedHeader,
+    handoff::{self, Handoff},
+    ip::{self, ClientIp, TrustedProxies},
+    listener::{self, ListenFds, SocketListener},
+    middleware::layer::TrustedProxyLayer,
+    rate_limit::{memory::MemoryStore, RateLimitLayer},
+    server::Server,
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+async fn handler(client: ClientIp) -> Response {
+    (format!("Hello, {}!", client.ip())).into_response()
+}
+
+/// Trusts the networks of `TRUSTED_PROXIES` and those listed in the `TRUSTED_PROXIES_FILE`.
+///
+/// Only the file can change while the server runs, it is read again on reload.
+fn load_trusted_proxies() -> Result<TrustedProxies, ip::Error> {
+    let proxies = TrustedProxies::from_env("TRUSTED_PROXIES")?;
+    match std::env::var_os("TRUSTED_PROXIES_FILE") {
+        Some(path) => proxies.load_file(path),
+        None => Ok(proxies),
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    let trusted_proxies = TrustedProxies::default();
-    let layer = TrustedProxyLayer::new(Arc::new(trusted_proxies));
+fn main() {
+    // A new release started next to the running one takes over its sockets through
+    // HANDOFF_SOCKET. They are taken before the runtime starts its threads, as taking them
+    // unsets the variables naming them.
+    let handoff_socket = std::env::var("HANDOFF_SOCKET").ok();
+    let listen_fds = match handoff_socket.as_deref().map(ListenFds::receive) {
+        Some(Ok(listen_fds)) => listen_fds,
+        // Nothing serves the socket on the first start, or after the previous process exited.
+        Some(Err(listener::Error::Handoff(handoff::Error::Io(error))))
+            if matches!(
+                error.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            eprintln!("Nothing serves HANDOFF_SOCKET, starting without a handoff: {error}");
+            ListenFds::from_env().expect("invalid listen file descriptors")
+        }
+        Some(Err(error)) => panic!("failed to take over from the previous process: {error:?}"),
+        None => ListenFds::from_env().expect("invalid listen file descriptors"),
+    };
 
-    let router = Router::new().route("/", routing::get(handler)).layer(layer);
+    tokio::runtime::Runtime::new()
+        .expect("failed to start the runtime")
+        .block_on(run(listen_fds, handoff_socket));
+}
 
-    let socket_listener = SocketListener::new("127.0.0.1:8080")
-        .await
-        .expect("failed to create listener");
+async fn run(mut listen_fds: ListenFds, handoff_socket: Option<String>) {
+    let trusted_proxies = load_trusted_proxies().expect("invalid trusted proxies");
+    // The header the proxies set, the others are ignored as clients can forge them.
+    let header = match std::env::var("FORWARDED_HEADER").as_deref() {
+        Ok("forwarded") => ForwardedHeader::Forwarded,
+        Ok("x-real-ip") => ForwardedHeader::XRealIp,
+        Ok("x-forwarded-for") | Err(_) => ForwardedHeader::XForwardedFor,
+        Ok(header) => panic!("invalid FORWARDED_HEADER {header:?}"),
+    };
+    let layer = TrustedProxyLayer::new(Arc::new(trusted_proxies)).with_forwarded_header(header);
+    let proxies = layer.handle();
+
+    let mut router = Router::new().route("/", routing::get(handler));
+    // Laravel's throttle syntax, e.g. RATE_LIMIT=60,1 for 60 requests per minute and client IP.
+    if let Ok(quota) = std::env::var("RATE_LIMIT") {
+        let quota = quota.parse().expect("invalid RATE_LIMIT");
+        router = router.layer(RateLimitLayer::new(MemoryStore::new(), quota));
+    }
+    let router = router.layer(layer);
+
+    let bind_addr = std::env::var("BIND_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_owned());
+    let socket_listener =
+        SocketListener::named(&mut listen_fds, "http", &bind_addr.as_str().into())
+            .await
+            .expect("failed to create listener");
+
+    println!(
+        "Server started with {}",
+        socket_listener.local_addr().unwrap()
+    );
+
+    let handoff = Handoff::new()
+        .with_listener("http", &socket_listener)
+        .expect("failed to prepare the socket handoff");
+    listen_fds
+        .complete_handoff()
+        .expect("failed to take over from the previous process");
+
+    // SIGUSR2 re-executes the binary, then this process drains once the new one is ready.
+    let replaced = async move {
+        let mut restart = signal(SignalKind::user_defined2()).expect("failed to handle SIGUSR2");
+        let restarted = async {
+            while restart.recv().await.is_some() {
+                match handoff.restart().await {
+                    Ok(()) => return,
+                    Err(error) => eprintln!("Failed to restart: {error}"),
+                }
+            }
+        };
+        let handed_off = async {
+            let Some(path) = handoff_socket.as_deref() else {
+                return std::future::pending().await;
+            };
+            if let Err(error) = handoff.serve(path).await {
+                eprintln!("Failed to serve the socket handoff: {error}");
+                std::future::pending::<()>().await;
+            }
+        };
 
-    let tcp_listener = socket_listener.into_inner();
+        tokio::select! {
+            () = restarted => {}
+            () = handed_off => {}
+        }
+        println!("Handed off the listener, draining");
+    };
 
-    println!("Server started with {}", tcp_listener.local_addr().unwrap());
+    let mut server = Server::new().with_shutdown_timeout(Duration::from_secs(8));
+    // Behind a load balancer in TCP mode, the trusted proxies send the PROXY protocol header.
+    if std::env::var("PROXY_PROTOCOL").is_ok_and(|value| value == "1") {
+        server = server.with_proxy_protocol(proxies.clone());
+    }
+    let server = server.on_reload(move || {
+        if let Err(error) = proxies.reload_with(load_trusted_proxies) {
+            eprintln!("Failed to reload the trusted proxies: {error}");
+        }
+    });
 
-    let value = router.into_make_service_with_connect_info::<ClientInfo>();
+    #[cfg(feature = "tls")]
+    let server = match (std::env::var("TLS_CERT"), std::env::var("TLS_KEY")) {
+        (Ok(cert), Ok(key)) => {
+            let tls = cortev_http::tls::TlsAcceptor::builder()
+                .with_certificate(cert, key)
+                .build()
+                .expect("failed to load the TLS certificate");
+            tokio::spawn(tls.clone().watch(Duration::from_secs(60)));
+            server.with_tls(tls)
+        }
+        _ => server,
+    };
 
-    axum::serve(tcp_listener, value)
+    server
+        .serve_with_shutdown(socket_listener, router, replaced)
         .await
         .expect("failed to start server");
 
@@ -1,5 +1,6 @@
 use std::{
     convert::Infallible,
+    fmt::{self, Debug, Formatter},
     sync::Arc,
     task::{Context, Poll},
 };
@@ -8,28 +9,65 @@ use axum::{
     extract::{self, ConnectInfo},
     response::{IntoResponse, Response},
 };
+use futures::future::{self, Either, Ready};
+use http::StatusCode;
 use tower_layer::Layer;
 use tower_service::Service;
 
-use crate::ip::{ClientInfo, TrustedProxies};
+use crate::{
+    forwarded::{self, ForwardedHeader},
+    ip::{self, ClientInfo, IpFilter, TrustedProxies, TrustedProxiesHandle},
+};
 
 use super::future::ResponseFuture;
 
+/// A layer resolving the [`ClientIp`](crate::ip::ClientIp) and
+/// [`EffectiveOrigin`](crate::forwarded::EffectiveOrigin) of each request.
+///
+/// The peer address comes from the `ClientInfo` connect info, and forwarding headers are only
+/// honoured when the peer is one of the trusted proxies. The peer itself remains available as
+/// `ClientInfo`.
+///
+/// Only `X-Forwarded-For` is read by default, see
+/// [`with_forwarded_header`](Self::with_forwarded_header) for proxies setting another header.
 #[derive(Debug, Clone)]
 pub struct TrustedProxyLayer {
-    trusted_proxies: Arc<TrustedProxies>,
+    trusted_proxies: TrustedProxiesHandle,
+    header: ForwardedHeader,
 }
 
 impl TrustedProxyLayer {
     pub fn new(trusted_proxies: Arc<TrustedProxies>) -> Self {
-        Self { trusted_proxies }
+        Self::with_handle(trusted_proxies.into())
+    }
+
+    /// Creates a layer whose trusted proxies can be reloaded through `handle`.
+    pub fn with_handle(handle: TrustedProxiesHandle) -> Self {
+        Self {
+            trusted_proxies: handle,
+            header: ForwardedHeader::default(),
+        }
+    }
+
+    /// Sets the header the trusted proxies pass the client address in. The other forwarding
+    /// headers are ignored, as the client can set them.
+    #[must_use]
+    pub fn with_forwarded_header(mut self, header: ForwardedHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Returns a handle to reload the trusted proxies of this layer.
+    pub fn handle(&self) -> TrustedProxiesHandle {
+        self.trusted_proxies.clone()
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct TrustedProxyMiddleware<S> {
     inner: S,
-    trusted_proxies: Arc<TrustedProxies>,
+    trusted_proxies: TrustedProxiesHandle,
+    header: ForwardedHeader,
 }
 
 impl<S> Layer<S> for TrustedProxyLayer {
@@ -39,6 +77,7 @@ impl<S> Layer<S> for TrustedProxyLayer {
         TrustedProxyMiddleware {
             inner,
             trusted_proxies: self.trusted_proxies.clone(),
+            header: self.header,
         }
     }
 }
@@ -59,16 +98,18 @@ where
     }
 
     fn call(&mut self, mut req: extract::Request) -> Self::Future {
-        let proxies = Arc::clone(&self.trusted_proxies);
+        let proxies = self.trusted_proxies.current();
         let ip_addr = req
             .extensions_mut()
             .remove::<ConnectInfo<ClientInfo>>()
             .map(|info| *info);
 
         if let Some(client_info) = ip_addr {
-            if !proxies.is_trusted(client_info.ip()) {
-                req.extensions_mut().insert(client_info);
-            }
+            let (client_ip, origin) =
+                forwarded::resolve(*client_info.ip(), req.headers(), &proxies, self.header);
+            req.extensions_mut().insert(client_info);
+            req.extensions_mut().insert(client_ip);
+            req.extensions_mut().insert(origin);
         }
 
         ResponseFuture {
@@ -76,3 +117,215 @@ where
         }
     }
 }
+
+type RejectionFn = Arc<dyn Fn(&extract::Request) -> Response + Send + Sync>;
+
+/// A layer letting through only the requests whose client IP passes an [`IpFilter`].
+///
+/// The client IP is the one resolved by the [`TrustedProxyLayer`], which must wrap this layer, or
+/// the peer address without it. Requests without either are rejected. Installed on a nested
+/// router, the filter only applies to that group of routes:
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use axum::{routing, Router};
+/// # use cortev_http::{ip::{IpFilter, TrustedProxies}, middleware::layer::*};
+/// let office_vpn = IpFilter::new().allow_cidrs("10.8.0.0/16")?;
+/// let admin = Router::new()
+///     .route("/", routing::get(|| async { "Admin" }))
+///     .layer(IpFilterLayer::new(office_vpn));
+///
+/// let app: Router = Router::new()
+///     .route("/", routing::get(|| async { "Hello!" }))
+///     .nest("/admin", admin)
+///     .layer(TrustedProxyLayer::new(Arc::new(TrustedProxies::new())));
+/// # Ok::<_, cortev_http::ip::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct IpFilterLayer {
+    filter: Arc<IpFilter>,
+    rejection: RejectionFn,
+}
+
+impl IpFilterLayer {
+    /// Creates a layer rejecting requests with `403 Forbidden`.
+    pub fn new(filter: IpFilter) -> Self {
+        Self {
+            filter: Arc::new(filter),
+            rejection: Arc::new(|_| (StatusCode::FORBIDDEN, "Forbidden").into_response()),
+        }
+    }
+
+    /// Sets the response of rejected requests.
+    pub fn with_rejection<F>(mut self, rejection: F) -> Self
+    where
+        F: Fn(&extract::Request) -> Response + Send + Sync + 'static,
+    {
+        self.rejection = Arc::new(rejection);
+        self
+    }
+}
+
+impl Debug for IpFilterLayer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IpFilterLayer")
+            .field("filter", &self.filter)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone)]
+pub struct IpFilterMiddleware<S> {
+    inner: S,
+    layer: IpFilterLayer,
+}
+
+impl<S: Debug> Debug for IpFilterMiddleware<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IpFilterMiddleware")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+impl<S> Layer<S> for IpFilterLayer {
+    type Service = IpFilterMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IpFilterMiddleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+impl<S> Service<extract::Request> for IpFilterMiddleware<S>
+where
+    S: Service<extract::Request, Response = Response, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<ResponseFuture<S::Future>, Ready<Result<Response, Infallible>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: extract::Request) -> Self::Future {
+        let allowed =
+            ip::client_ip(req.extensions()).is_some_and(|ip| self.layer.filter.is_allowed(&ip));
+
+        if allowed {
+            Either::Left(ResponseFuture {
+                future: self.inner.call(req),
+            })
+        } else {
+            Either::Right(future::ready(Ok((self.layer.rejection)(&req))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use axum::{body::Body, routing, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn request(uri: &str, peer: &str, forwarded_for: &str) -> extract::Request {
+        let mut request = extract::Request::builder()
+            .uri(uri)
+            .header("x-forwarded-for", forwarded_for)
+            .body(Body::empty())
+            .unwrap();
+        let peer: IpAddr = peer.parse().unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(ClientInfo::new(peer)));
+        request
+    }
+
+    #[tokio::test]
+    async fn test_ip_filter_per_route_group() {
+        let office_vpn = IpFilter::new().allow_cidrs("10.8.0.0/16").unwrap();
+        let admin = Router::new()
+            .route("/", routing::get(|| async { "admin" }))
+            .layer(IpFilterLayer::new(office_vpn));
+        let proxies = TrustedProxies::new().trust_cidrs("192.0.2.1").unwrap();
+        let router = Router::new()
+            .route("/", routing::get(|| async { "home" }))
+            .nest("/admin", admin)
+            .layer(TrustedProxyLayer::new(Arc::new(proxies)));
+
+        let cases = [
+            ("/", "203.0.113.7", "1.1.1.1", StatusCode::OK),
+            ("/admin", "10.8.0.5", "1.1.1.1", StatusCode::OK),
+            ("/admin", "203.0.113.7", "1.1.1.1", StatusCode::FORBIDDEN),
+            // Through the trusted proxy, the forwarded address is checked.
+            ("/admin", "192.0.2.1", "10.8.3.4", StatusCode::OK),
+            ("/admin", "192.0.2.1", "1.1.1.1", StatusCode::FORBIDDEN),
+            // An untrusted peer cannot spoof the office range.
+            ("/admin", "203.0.113.7", "10.8.3.4", StatusCode::FORBIDDEN),
+        ];
+        for (uri, peer, forwarded_for, status) in cases {
+            let response = router
+                .clone()
+                .oneshot(request(uri, peer, forwarded_for))
+                .await
+                .unwrap();
+            assert_eq!(
+                response.status(),
+                status,
+                "{uri} from {peer} for {forwarded_for}"
+            );
+        }
+
+        // The proxy only appends to `X-Forwarded-For`, a `Forwarded` header is the client's.
+        let mut spoofed = request("/admin", "192.0.2.1", "1.1.1.1");
+        spoofed
+            .headers_mut()
+            .insert(http::header::FORWARDED, "for=10.8.3.4".parse().unwrap());
+        let response = router.clone().oneshot(spoofed).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_ip_filter_rejection() {
+        let layer = IpFilterLayer::new(IpFilter::new().deny_cidrs("203.0.113.0/24").unwrap())
+            .with_rejection(|req| {
+                (StatusCode::NOT_FOUND, format!("{} not found", req.uri())).into_response()
+            });
+        let router = Router::new()
+            .route("/", routing::get(|| async { "home" }))
+            .layer(layer);
+
+        let response = router
+            .clone()
+            .oneshot(request("/", "203.0.113.7", "1.1.1.1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "/ not found");
+
+        let response = router
+            .clone()
+            .oneshot(request("/", "198.51.100.1", "1.1.1.1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Without connect info the client is unknown and rejected.
+        let response = router
+            .oneshot(extract::Request::new(Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
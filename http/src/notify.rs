@@ -0,0 +1,136 @@
+//! Service manager notifications, see
+//! [`sd_notify(3)`](https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html).
+//!
+//! Every function is a no-op returning `Ok(false)` when the process was not started by a service
+//! manager listening on `NOTIFY_SOCKET`.
+use std::{
+    io,
+    os::unix::net::UnixDatagram,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+
+/// Set once a new process took over as main process of the service, see
+/// [`Handoff`](crate::handoff::Handoff).
+static HANDED_OFF: AtomicBool = AtomicBool::new(false);
+
+/// Tells the service manager that startup is finished.
+pub fn ready() -> io::Result<bool> {
+    notify("READY=1")
+}
+
+/// Tells the service manager that the service is reloading its configuration.
+///
+/// Must be followed by [`ready`] once the reload is complete.
+pub fn reloading() -> io::Result<bool> {
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={}", monotonic_usec()))
+}
+
+/// Tells the service manager that the service is shutting down.
+///
+/// Does nothing once the listeners were handed off, the service keeps running in the new
+/// process and would be stopped with this one otherwise.
+pub fn stopping() -> io::Result<bool> {
+    if handed_off() {
+        return Ok(false);
+    }
+    notify("STOPPING=1")
+}
+
+/// Resets the watchdog timer of the service manager.
+pub fn watchdog() -> io::Result<bool> {
+    notify("WATCHDOG=1")
+}
+
+/// Sends `state`, newline separated `KEY=VALUE` assignments, to the service manager.
+///
+/// # Errors
+/// Returns an error if `NOTIFY_SOCKET` is set but the message cannot be sent.
+pub fn notify(state: &str) -> io::Result<bool> {
+    match std::env::var_os(NOTIFY_SOCKET) {
+        Some(path) => notify_to(Path::new(&path), state).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+/// Returns how often [`watchdog`] must be called, or `None` if the watchdog is disabled.
+///
+/// This is half of `WATCHDOG_USEC`, as recommended, and only if `WATCHDOG_PID` is unset or is
+/// the current process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Records that a new process took over as main process of the service.
+pub(crate) fn set_handed_off() {
+    HANDED_OFF.store(true, Ordering::Release);
+}
+
+pub(crate) fn handed_off() -> bool {
+    HANDED_OFF.load(Ordering::Acquire)
+}
+
+fn notify_to(path: &Path, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+
+    match path.as_os_str().as_encoded_bytes() {
+        [b'@', name @ ..] => send_abstract(&socket, name, state),
+        _ => socket.send_to(state.as_bytes(), path).map(|_| ()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(socket: &UnixDatagram, name: &[u8], state: &str) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    socket.send_to_addr(state.as_bytes(), &addr).map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_socket: &UnixDatagram, _name: &[u8], _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract notify sockets are only supported on Linux",
+    ))
+}
+
+fn monotonic_usec() -> u64 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Safety: `time` is a valid timespec and CLOCK_MONOTONIC is always supported.
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    time.tv_sec as u64 * 1_000_000 + time.tv_nsec as u64 / 1_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_to() {
+        let path = std::env::temp_dir().join(format!("notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        notify_to(&path, "READY=1").unwrap();
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+//! The [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt), versions 1
+//! and 2, sent by load balancers in TCP mode such as HAProxy or an AWS NLB.
+//!
+//! The header precedes any other data on the connection and carries the address of the client,
+//! which the load balancer would otherwise hide.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The longest version 1 header, including the trailing CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// Reads the PROXY protocol header at the start of `stream`.
+///
+/// Returns the source address, or `None` for connections made by the proxy itself, such as
+/// health checks, and for sources that are not IP addresses. Nothing past the header is read.
+///
+/// # Errors
+/// Returns `Error::Missing` if the stream does not start with a header and `Error::Invalid` if
+/// the header is malformed.
+pub async fn read_header<S>(stream: &mut S) -> Result<Option<SocketAddr>, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut start = [0; 12];
+    stream.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else {
+        Err(Error::Missing)
+    }
+}
+
+async fn read_v1<S>(stream: &mut S, start: &[u8]) -> Result<Option<SocketAddr>, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    // The header has no length, so it is read a byte at a time to leave the request untouched.
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(Error::Invalid);
+        }
+        line.push(stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| Error::Invalid)?;
+    parse_v1(line)
+}
+
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>, Error> {
+    let mut parts = line.split(' ').skip(1);
+
+    match parts.next() {
+        Some("UNKNOWN") => return Ok(None),
+        Some("TCP4" | "TCP6") => {}
+        _ => return Err(Error::Invalid),
+    }
+
+    let mut next = || parts.next().ok_or(Error::Invalid);
+    let source: IpAddr = next()?.parse().map_err(|_| Error::Invalid)?;
+    let _destination: IpAddr = next()?.parse().map_err(|_| Error::Invalid)?;
+    let port: u16 = next()?.parse().map_err(|_| Error::Invalid)?;
+    let _destination_port: u16 = next()?.parse().map_err(|_| Error::Invalid)?;
+
+    Ok(Some(SocketAddr::new(source, port)))
+}
+
+async fn read_v2<S>(stream: &mut S) -> Result<Option<SocketAddr>, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).await?;
+    let [version_command, family, len @ ..] = header;
+
+    let mut payload = vec![0; u16::from_be_bytes(len).into()];
+    stream.read_exact(&mut payload).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(Error::Invalid);
+    }
+    match version_command & 0x0f {
+        // LOCAL, the proxy connected on its own behalf.
+        0 => return Ok(None),
+        // PROXY
+        1 => {}
+        _ => return Err(Error::Invalid),
+    }
+
+    // The high nibble is the address family, the low one the transport, ignored here.
+    match family >> 4 {
+        // AF_INET
+        1 => {
+            let addresses: [u8; 12] = prefix(&payload)?;
+            let [a, b, c, d, _, _, _, _, p1, p2, _, _] = addresses;
+            let ip = Ipv4Addr::new(a, b, c, d);
+            Ok(Some(SocketAddr::new(
+                ip.into(),
+                u16::from_be_bytes([p1, p2]),
+            )))
+        }
+        // AF_INET6
+        2 => {
+            let addresses: [u8; 36] = prefix(&payload)?;
+            let source: [u8; 16] = prefix(&addresses)?;
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(source).into(), port)))
+        }
+        // AF_UNSPEC and AF_UNIX
+        0 | 3 => Ok(None),
+        _ => Err(Error::Invalid),
+    }
+}
+
+fn prefix<const N: usize>(payload: &[u8]) -> Result<[u8; N], Error> {
+    payload
+        .get(..N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(Error::Invalid)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("the connection does not start with a PROXY protocol header")]
+    Missing,
+
+    #[error("the PROXY protocol header is invalid")]
+    Invalid,
+
+    #[error("failed to read the PROXY protocol header")]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(mut data: &[u8]) -> (Result<Option<SocketAddr>, Error>, &[u8]) {
+        let result = read_header(&mut data).await;
+        (result, data)
+    }
+
+    #[tokio::test]
+    async fn test_v1() {
+        let (result, rest) = read(b"PROXY TCP4 203.0.113.7 10.0.0.1 56324 443\r\nGET /").await;
+        assert_eq!(result.unwrap(), Some("203.0.113.7:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let (result, _) = read(b"PROXY TCP6 2001:db8::1 ::1 4000 80\r\n").await;
+        assert_eq!(result.unwrap(), Some("[2001:db8::1]:4000".parse().unwrap()));
+
+        let (result, _) = read(b"PROXY UNKNOWN\r\n").await;
+        assert_eq!(result.unwrap(), None);
+
+        let (result, _) = read(b"PROXY TCP4 nope 10.0.0.1 1 2\r\n").await;
+        assert!(matches!(result, Err(Error::Invalid)));
+
+        let (result, _) = read(b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(matches!(result, Err(Error::Missing)));
+    }
+
+    #[tokio::test]
+    async fn test_v2() {
+        let mut data = V2_SIGNATURE.to_vec();
+        // PROXY over TCP4, with a TLV after the addresses.
+        data.extend([0x21, 0x11, 0, 15]);
+        data.extend([203, 0, 113, 7, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb]);
+        data.extend([0x04, 0, 0]);
+        data.extend(b"GET /");
+
+        let (result, rest) = read(&data).await;
+        assert_eq!(result.unwrap(), Some("203.0.113.7:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend([0x20, 0x00, 0, 0]);
+        let (result, _) = read(&local).await;
+        assert_eq!(result.unwrap(), None);
+
+        let mut truncated = V2_SIGNATURE.to_vec();
+        truncated.extend([0x21, 0x21, 0, 4, 0, 0, 0, 0]);
+        let (result, _) = read(&truncated).await;
+        assert!(matches!(result, Err(Error::Invalid)));
+    }
+}
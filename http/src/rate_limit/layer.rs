@@ -0,0 +1,330 @@
+use std::{
+    convert::Infallible,
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use http::StatusCode;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::ip;
+
+use super::{Decision, Quota, RateLimitStore};
+
+type KeyFn = Arc<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+type RejectionFn = Arc<dyn Fn(&Decision) -> Response + Send + Sync>;
+
+/// A layer limiting the number of requests per key, by default the client IP.
+///
+/// The client IP is the one resolved by the
+/// [`TrustedProxyLayer`](crate::middleware::layer::TrustedProxyLayer), which must wrap this layer,
+/// or the peer address without it. Responses carry the `RateLimit-*` headers of the
+/// [`Decision`], and rejected requests get a `429 Too Many Requests` response with `Retry-After`.
+///
+/// Requests are let through without headers when the store fails, so an unreachable Redis server
+/// does not take the application down, unless the layer [fails closed](Self::with_fail_closed).
+pub struct RateLimitLayer<T> {
+    store: Arc<T>,
+    quota: Quota,
+    prefix: Arc<str>,
+    key: KeyFn,
+    rejection: RejectionFn,
+    fail_closed: bool,
+}
+
+impl<T> RateLimitLayer<T> {
+    pub fn new(store: T, quota: Quota) -> Self {
+        Self {
+            store: Arc::new(store),
+            quota,
+            prefix: "global".into(),
+            key: Arc::new(by_ip),
+            rejection: Arc::new(|_| {
+                (StatusCode::TOO_MANY_REQUESTS, "Too Many Requests").into_response()
+            }),
+            fail_closed: false,
+        }
+    }
+
+    /// Sets the name of this limiter, so layers sharing a store keep separate counts.
+    pub fn with_prefix(mut self, prefix: impl Into<Arc<str>>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Limits each authenticated user, identified by `user`, and each IP for guests.
+    ///
+    /// ```
+    /// # use cortev_http::rate_limit::{memory::MemoryStore, Quota, RateLimitLayer};
+    /// #[derive(Clone)]
+    /// struct UserId(u64);
+    ///
+    /// let layer = RateLimitLayer::new(MemoryStore::new(), Quota::per_minute(60))
+    ///     .by_user(|request| request.extensions().get::<UserId>().map(|user| user.0.to_string()));
+    /// ```
+    pub fn by_user<F>(mut self, user: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(move |request| match user(request) {
+            Some(user) => Some(format!("user:{user}")),
+            None => by_ip(request),
+        });
+        self
+    }
+
+    /// Limits requests by a custom key, requests without a key are not limited.
+    pub fn by_key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    /// Sets the response of rejected requests, the rate limit headers are added to it.
+    pub fn with_rejection<F>(mut self, rejection: F) -> Self
+    where
+        F: Fn(&Decision) -> Response + Send + Sync + 'static,
+    {
+        self.rejection = Arc::new(rejection);
+        self
+    }
+
+    /// Rejects requests with `503 Service Unavailable` when the store fails, for limits guarding
+    /// against abuse, such as login attempts, which must hold even without the store.
+    pub fn with_fail_closed(mut self) -> Self {
+        self.fail_closed = true;
+        self
+    }
+}
+
+fn by_ip(request: &Request) -> Option<String> {
+    ip::client_ip(request.extensions()).map(|ip| format!("ip:{ip}"))
+}
+
+impl<T> Clone for RateLimitLayer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            quota: self.quota,
+            prefix: self.prefix.clone(),
+            key: self.key.clone(),
+            rejection: self.rejection.clone(),
+            fail_closed: self.fail_closed,
+        }
+    }
+}
+
+impl<T: Debug> Debug for RateLimitLayer<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimitLayer")
+            .field("store", &self.store)
+            .field("quota", &self.quota)
+            .field("prefix", &self.prefix)
+            .field("fail_closed", &self.fail_closed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, T> Layer<S> for RateLimitLayer<T> {
+    type Service = RateLimitMiddleware<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitMiddleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+pub struct RateLimitMiddleware<S, T> {
+    inner: S,
+    layer: RateLimitLayer<T>,
+}
+
+impl<S: Clone, T> Clone for RateLimitMiddleware<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S: Debug, T: Debug> Debug for RateLimitMiddleware<S, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimitMiddleware")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+impl<S, T> Service<Request> for RateLimitMiddleware<S, T>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    T: RateLimitStore,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // The clone may not be ready, so the ready service is taken and the clone left behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            let Some(key) = (layer.key)(&request) else {
+                return inner.call(request).await;
+            };
+            let key = format!("{}:{key}", layer.prefix);
+            let decision = match layer.store.hit(&key, &layer.quota).await {
+                Ok(decision) => decision,
+                Err(error) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        error = &error as &dyn std::error::Error,
+                        fail_closed = layer.fail_closed,
+                        "failed to check the rate limit"
+                    );
+                    #[cfg(not(feature = "tracing"))]
+                    let _ = error;
+
+                    if layer.fail_closed {
+                        let unavailable = (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable");
+                        return Ok(unavailable.into_response());
+                    }
+                    return inner.call(request).await;
+                }
+            };
+
+            let mut response = if decision.is_allowed() {
+                inner.call(request).await?
+            } else {
+                (layer.rejection)(&decision)
+            };
+            decision.apply(response.headers_mut());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use axum::{body::Body, extract::ConnectInfo, routing, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        ip::ClientInfo,
+        rate_limit::{memory::MemoryStore, Error},
+    };
+
+    struct FailingStore;
+
+    impl RateLimitStore for FailingStore {
+        async fn hit(&self, _key: &str, _quota: &Quota) -> Result<Decision, Error> {
+            Err(Error::InvalidQuota("unreachable".to_owned()))
+        }
+    }
+
+    fn request(peer: [u8; 4], user: Option<&str>) -> Request {
+        let mut request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(ClientInfo::new(IpAddr::V4(Ipv4Addr::from(
+                peer,
+            )))));
+        if let Some(user) = user {
+            request.extensions_mut().insert(user.to_owned());
+        }
+        request
+    }
+
+    async fn statuses(router: &Router, requests: Vec<Request>) -> Vec<u16> {
+        let mut statuses = Vec::new();
+        for request in requests {
+            let response = router.clone().oneshot(request).await.unwrap();
+            statuses.push(response.status().as_u16());
+        }
+        statuses
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_by_ip() {
+        let router = Router::new()
+            .route("/", routing::get(|| async { "ok" }))
+            .layer(RateLimitLayer::new(
+                MemoryStore::new(),
+                Quota::per_minute(2),
+            ));
+
+        let requests = vec![
+            request([10, 0, 0, 1], None),
+            request([10, 0, 0, 1], None),
+            request([10, 0, 0, 2], None),
+        ];
+        assert_eq!(statuses(&router, requests).await, [200, 200, 200]);
+
+        let response = router.oneshot(request([10, 0, 0, 1], None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["ratelimit-limit"], "2");
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+        assert_eq!(response.headers()["retry-after"], "60");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_by_user() {
+        let layer = RateLimitLayer::new(MemoryStore::new(), Quota::per_minute(1))
+            .by_user(|request| request.extensions().get::<String>().cloned())
+            .with_rejection(|decision| {
+                let retry_after = decision.retry_after().unwrap_or_default().as_secs();
+                (StatusCode::SERVICE_UNAVAILABLE, format!("{retry_after}")).into_response()
+            });
+        let router = Router::new()
+            .route("/", routing::get(|| async { "ok" }))
+            .layer(layer);
+
+        // The same user from two addresses, then guests limited by address.
+        let requests = vec![
+            request([10, 0, 0, 1], Some("alice")),
+            request([10, 0, 0, 2], Some("alice")),
+            request([10, 0, 0, 1], None),
+            request([10, 0, 0, 1], None),
+            request([10, 0, 0, 2], Some("bob")),
+        ];
+        assert_eq!(statuses(&router, requests).await, [200, 503, 200, 503, 200]);
+    }
+
+    #[tokio::test]
+    async fn test_store_failure() {
+        let route = Router::new().route("/", routing::get(|| async { "ok" }));
+        let layer = RateLimitLayer::new(FailingStore, Quota::per_minute(1));
+
+        let router = route.clone().layer(layer.clone());
+        let response = router.oneshot(request([10, 0, 0, 1], None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key("ratelimit-limit"));
+
+        let router = route.layer(layer.with_fail_closed());
+        let response = router.oneshot(request([10, 0, 0, 1], None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
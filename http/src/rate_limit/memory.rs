@@ -0,0 +1,238 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::Instant,
+};
+
+use super::{Algorithm, Decision, Error, Quota, RateLimitStore};
+
+/// The number of hits between two sweeps of the expired entries.
+const PRUNE_INTERVAL: u64 = 1024;
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    FixedWindow {
+        start: u64,
+        count: u64,
+    },
+    SlidingWindow {
+        start: u64,
+        current: u64,
+        previous: u64,
+    },
+    TokenBucket {
+        tokens: f64,
+        updated: u64,
+    },
+}
+
+#[derive(Debug)]
+struct Entry {
+    state: State,
+    /// The instant, in milliseconds, after which the entry holds nothing worth keeping.
+    expires_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    entries: HashMap<String, Entry>,
+    hits: u64,
+}
+
+/// A store keeping the counts in memory, local to this process.
+///
+/// Counts are not shared between the processes of a deployment, use the
+/// [`RedisStore`](super::redis::RedisStore) for that.
+#[derive(Debug, Clone)]
+pub struct MemoryStore {
+    entries: Arc<Mutex<Entries>>,
+    started: Instant,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn hit_at(&self, key: &str, quota: &Quota, now: u64) -> Decision {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+        entries.hits += 1;
+        if entries.hits.is_multiple_of(PRUNE_INTERVAL) {
+            entries.entries.retain(|_, entry| entry.expires_at > now);
+        }
+
+        let state = entries
+            .entries
+            .get(key)
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| entry.state);
+        let (state, expires_at, decision) = hit(state, quota, now);
+        entries
+            .entries
+            .insert(key.to_owned(), Entry { state, expires_at });
+
+        decision
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self {
+            entries: Arc::default(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    async fn hit(&self, key: &str, quota: &Quota) -> Result<Decision, Error> {
+        let now = self
+            .started
+            .elapsed()
+            .as_millis()
+            .try_into()
+            .unwrap_or(u64::MAX);
+        Ok(self.hit_at(key, quota, now))
+    }
+}
+
+/// Records a request at `now` and returns the new state, when it expires and the decision.
+fn hit(state: Option<State>, quota: &Quota, now: u64) -> (State, u64, Decision) {
+    let limit = u64::from(quota.limit());
+    let period = quota.period_millis();
+
+    match (quota.algorithm(), state) {
+        (Algorithm::FixedWindow, state) => {
+            let (start, mut count) = match state {
+                Some(State::FixedWindow { start, count }) if now - start < period => (start, count),
+                _ => (now, 0),
+            };
+            let allowed = count < limit;
+            count += u64::from(allowed);
+
+            let decision = super::fixed_window(quota, count, now - start, allowed);
+            (
+                State::FixedWindow { start, count },
+                start + period,
+                decision,
+            )
+        }
+        (Algorithm::SlidingWindow, state) => {
+            let (mut start, mut current, mut previous) = match state {
+                Some(State::SlidingWindow {
+                    start,
+                    current,
+                    previous,
+                }) => (start, current, previous),
+                _ => (now, 0, 0),
+            };
+            match (now - start) / period {
+                0 => {}
+                1 => (previous, current) = (current, 0),
+                _ => (previous, current) = (0, 0),
+            }
+            start += (now - start) / period * period;
+
+            let elapsed = now - start;
+            let used = u128::from(previous) * u128::from(period - elapsed)
+                + u128::from(current + 1) * u128::from(period);
+            let allowed = used <= u128::from(limit) * u128::from(period);
+            current += u64::from(allowed);
+
+            let decision = super::sliding_window(quota, current, previous, elapsed, allowed);
+            let state = State::SlidingWindow {
+                start,
+                current,
+                previous,
+            };
+            (state, start + 2 * period, decision)
+        }
+        (Algorithm::TokenBucket, state) => {
+            let limit = limit as f64;
+            let mut tokens = match state {
+                Some(State::TokenBucket { tokens, updated }) => {
+                    let refilled = (now - updated) as f64 * limit / period as f64;
+                    (tokens + refilled).min(limit)
+                }
+                _ => limit,
+            };
+            let allowed = tokens >= 1.0;
+            if allowed {
+                tokens -= 1.0;
+            }
+
+            let decision = super::token_bucket(quota, tokens, allowed);
+            let full_at = now + decision.reset().as_millis() as u64;
+            let state = State::TokenBucket {
+                tokens,
+                updated: now,
+            };
+            (state, full_at, decision)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn hits(store: &MemoryStore, quota: &Quota, now: u64, count: usize) -> Vec<bool> {
+        (0..count)
+            .map(|_| store.hit_at("key", quota, now).is_allowed())
+            .collect()
+    }
+
+    #[test]
+    fn test_fixed_window() {
+        let store = MemoryStore::new();
+        let quota = Quota::new(2, Duration::from_secs(10));
+
+        assert_eq!(hits(&store, &quota, 1_000, 3), [true, true, false]);
+
+        let decision = store.hit_at("key", &quota, 4_000);
+        assert_eq!(decision.retry_after(), Some(Duration::from_secs(7)));
+        assert!(store.hit_at("other", &quota, 4_000).is_allowed());
+
+        assert_eq!(hits(&store, &quota, 11_000, 3), [true, true, false]);
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let store = MemoryStore::new();
+        let quota = Quota::new(4, Duration::from_secs(10)).with_algorithm(Algorithm::SlidingWindow);
+
+        assert_eq!(hits(&store, &quota, 0, 1), [true]);
+        assert_eq!(hits(&store, &quota, 9_000, 4), [true, true, true, false]);
+
+        // A fixed window would allow 4 more, but most of the last period is still counted.
+        assert_eq!(hits(&store, &quota, 12_000, 1), [false]);
+        assert_eq!(hits(&store, &quota, 13_000, 2), [true, false]);
+        let decision = store.hit_at("key", &quota, 13_000);
+        assert_eq!(decision.retry_after(), Some(Duration::from_secs(2)));
+        assert_eq!(hits(&store, &quota, 15_000, 2), [true, false]);
+
+        assert_eq!(
+            hits(&store, &quota, 30_000, 5),
+            [true, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let store = MemoryStore::new();
+        let quota = Quota::new(2, Duration::from_secs(10)).with_algorithm(Algorithm::TokenBucket);
+
+        assert_eq!(hits(&store, &quota, 0, 3), [true, true, false]);
+        let decision = store.hit_at("key", &quota, 1_000);
+        assert_eq!(decision.retry_after(), Some(Duration::from_secs(4)));
+
+        // A token is back every 5 seconds.
+        assert_eq!(hits(&store, &quota, 5_000, 2), [true, false]);
+        let decision = store.hit_at("key", &quota, 100_000);
+        assert_eq!(decision.remaining(), 1);
+        assert_eq!(decision.reset(), Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,218 @@
+//! Rate limiting of requests, in the spirit of Laravel's `throttle:60,1` middleware.
+//!
+//! A [`RateLimitLayer`] counts the requests of each key, by default the resolved client IP, in a
+//! [`RateLimitStore`] and rejects those exceeding the [`Quota`] with `429 Too Many Requests`.
+//!
+//! ```no_run
+//! # use axum::{routing, Router};
+//! # use cortev_http::rate_limit::{memory::MemoryStore, RateLimitLayer};
+//! let api: Router = Router::new()
+//!     .route("/", routing::get(|| async { "Hello!" }))
+//!     .layer(RateLimitLayer::new(MemoryStore::new(), "60,1".parse()?));
+//! # Ok::<_, cortev_http::rate_limit::Error>(())
+//! ```
+use std::{future::Future, time::Duration};
+
+use http::{HeaderMap, HeaderName, HeaderValue};
+use thiserror::Error;
+
+pub mod layer;
+pub mod memory;
+pub mod quota;
+#[cfg(feature = "redis")]
+pub mod redis;
+
+pub use layer::RateLimitLayer;
+pub use quota::{Algorithm, Quota};
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// A store keeping track of the requests made by each key.
+pub trait RateLimitStore: Send + Sync + 'static {
+    /// Records a request for `key` if the quota allows it.
+    fn hit(&self, key: &str, quota: &Quota)
+        -> impl Future<Output = Result<Decision, Error>> + Send;
+}
+
+/// The outcome of a request against a quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    allowed: bool,
+    limit: u32,
+    remaining: u32,
+    reset: Duration,
+    retry_after: Option<Duration>,
+}
+
+impl Decision {
+    pub fn is_allowed(&self) -> bool {
+        self.allowed
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    /// Returns the number of requests still allowed before the quota is exceeded.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Returns the time until the quota is fully available again.
+    pub fn reset(&self) -> Duration {
+        self.reset
+    }
+
+    /// Returns the time until a request would be allowed, for rejected requests.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// Adds the `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset` and, for rejected
+    /// requests, `Retry-After` headers, all in whole seconds.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert(RATELIMIT_LIMIT, self.limit.into());
+        headers.insert(RATELIMIT_REMAINING, self.remaining.into());
+        headers.insert(RATELIMIT_RESET, seconds(self.reset));
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(http::header::RETRY_AFTER, seconds(retry_after));
+        }
+    }
+}
+
+fn seconds(duration: Duration) -> HeaderValue {
+    let seconds = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+    seconds.into()
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0:?} is not a valid quota")]
+    InvalidQuota(String),
+
+    #[cfg(feature = "redis")]
+    #[error("failed to reach the rate limit store")]
+    Redis(#[from] ::redis::RedisError),
+}
+
+/// Builds the decision of a fixed window, `elapsed` milliseconds after it started.
+fn fixed_window(quota: &Quota, count: u64, elapsed: u64, allowed: bool) -> Decision {
+    let reset = quota.period_millis().saturating_sub(elapsed);
+    Decision {
+        allowed,
+        limit: quota.limit(),
+        remaining: u64::from(quota.limit())
+            .saturating_sub(count)
+            .try_into()
+            .unwrap_or(0),
+        reset: Duration::from_millis(reset),
+        retry_after: (!allowed).then(|| Duration::from_millis(reset)),
+    }
+}
+
+/// Builds the decision of a sliding window, `elapsed` milliseconds into the current window.
+///
+/// The requests of the previous window are weighted by how much of it still overlaps the last
+/// period, so a burst at the end of a window cannot be repeated right after it.
+fn sliding_window(
+    quota: &Quota,
+    current: u64,
+    previous: u64,
+    elapsed: u64,
+    allowed: bool,
+) -> Decision {
+    let limit = u128::from(quota.limit());
+    let period = u128::from(quota.period_millis());
+    let (current, previous, elapsed) = (
+        u128::from(current),
+        u128::from(previous),
+        u128::from(elapsed),
+    );
+    let reset = period.saturating_sub(elapsed);
+
+    let used = previous * reset + current * period;
+    let remaining = (limit * period).saturating_sub(used) / period;
+
+    let retry_after = (!allowed).then(|| {
+        if current < limit {
+            // Wait for enough of the previous window to slide out.
+            let available = (limit - current - 1) * period;
+            let overlap = available
+                .checked_div(previous)
+                .unwrap_or(period)
+                .min(period);
+            (period - overlap).saturating_sub(elapsed)
+        } else {
+            // Wait for the next window, where the current one becomes the previous.
+            let available = (limit - 1) * period;
+            reset + period - (available / current).min(period)
+        }
+    });
+
+    let millis = |value: u128| Duration::from_millis(value.try_into().unwrap_or(u64::MAX));
+    Decision {
+        allowed,
+        limit: quota.limit(),
+        remaining: remaining.try_into().unwrap_or(u32::MAX),
+        reset: millis(reset),
+        retry_after: retry_after.map(millis),
+    }
+}
+
+/// Builds the decision of a token bucket holding `tokens` after the request.
+fn token_bucket(quota: &Quota, tokens: f64, allowed: bool) -> Decision {
+    // Tokens refill at `limit` per period, in milliseconds per token.
+    let refill = quota.period_millis() as f64 / f64::from(quota.limit());
+    let millis = |tokens: f64| Duration::from_millis((tokens * refill).ceil() as u64);
+
+    Decision {
+        allowed,
+        limit: quota.limit(),
+        remaining: tokens as u32,
+        reset: millis(f64::from(quota.limit()) - tokens),
+        retry_after: (!allowed).then(|| millis(1.0 - tokens)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        let quota = Quota::per_minute(60);
+        let mut headers = HeaderMap::new();
+        fixed_window(&quota, 60, 30_500, false).apply(&mut headers);
+
+        assert_eq!(headers["ratelimit-limit"], "60");
+        assert_eq!(headers["ratelimit-remaining"], "0");
+        assert_eq!(headers["ratelimit-reset"], "30");
+        assert_eq!(headers["retry-after"], "30");
+
+        let mut headers = HeaderMap::new();
+        fixed_window(&quota, 1, 0, true).apply(&mut headers);
+        assert_eq!(headers["ratelimit-remaining"], "59");
+        assert!(!headers.contains_key("retry-after"));
+    }
+
+    #[test]
+    fn test_sliding_window_retry_after() {
+        let quota =
+            Quota::new(10, Duration::from_secs(10)).with_algorithm(Algorithm::SlidingWindow);
+
+        // 10 requests in the previous window and 4 in this one, halfway through: 9 are counted.
+        let decision = sliding_window(&quota, 4, 10, 5_000, true);
+        assert_eq!(decision.remaining(), 1);
+
+        // 6 in the previous window and 7 in this one, 2s in: one more fits once 2/3 of the
+        // previous window has slid out.
+        let decision = sliding_window(&quota, 7, 6, 2_000, false);
+        assert_eq!(decision.retry_after(), Some(Duration::from_millis(4_667)));
+
+        // The window is full, so the next one must let enough of this one slide out.
+        let decision = sliding_window(&quota, 10, 0, 2_000, false);
+        assert_eq!(decision.retry_after(), Some(Duration::from_millis(9_000)));
+    }
+}
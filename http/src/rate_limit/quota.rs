@@ -0,0 +1,124 @@
+use std::{str::FromStr, time::Duration};
+
+use super::Error;
+
+/// How requests are counted against a quota.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Counts requests in windows starting at the first request, like Laravel. Cheap, but allows
+    /// up to twice the limit across the boundary of two windows.
+    #[default]
+    FixedWindow,
+    /// Weights the count of the previous window by how much of it overlaps the last period.
+    SlidingWindow,
+    /// Allows bursts of up to the limit, refilled evenly over the period.
+    TokenBucket,
+}
+
+/// The number of requests allowed per period.
+///
+/// Parses from Laravel's `throttle` syntax, the limit and the period in minutes:
+///
+/// ```
+/// # use std::time::Duration;
+/// # use cortev_http::rate_limit::Quota;
+/// let quota: Quota = "60,1".parse()?;
+/// assert_eq!(quota, Quota::new(60, Duration::from_secs(60)));
+/// # Ok::<_, cortev_http::rate_limit::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    limit: u32,
+    period: Duration,
+    algorithm: Algorithm,
+}
+
+impl Quota {
+    /// Creates a quota of `limit` requests per `period`, counted in fixed windows.
+    ///
+    /// # Panics
+    /// Panics if `limit` is zero or `period` is shorter than a millisecond.
+    pub fn new(limit: u32, period: Duration) -> Self {
+        assert!(limit > 0, "the limit of a quota must not be zero");
+        assert!(
+            period >= Duration::from_millis(1),
+            "the period of a quota must be at least a millisecond"
+        );
+        Self {
+            limit,
+            period,
+            algorithm: Algorithm::default(),
+        }
+    }
+
+    pub fn per_second(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(60))
+    }
+
+    pub fn per_hour(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(60 * 60))
+    }
+
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub(crate) fn period_millis(&self) -> u64 {
+        self.period.as_millis().try_into().unwrap_or(u64::MAX)
+    }
+}
+
+impl FromStr for Quota {
+    type Err = Error;
+
+    /// Parses `limit,minutes`, where the minutes default to one.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidQuota(s.to_owned());
+        let (limit, minutes) = s.split_once(',').unwrap_or((s, "1"));
+
+        let limit: u32 = limit.trim().parse().map_err(|_| invalid())?;
+        let minutes: u64 = minutes.trim().parse().map_err(|_| invalid())?;
+        if limit == 0 || minutes == 0 {
+            return Err(invalid());
+        }
+
+        let period = minutes.checked_mul(60).ok_or_else(invalid)?;
+        Ok(Self::new(limit, Duration::from_secs(period)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("60,1".parse::<Quota>().unwrap(), Quota::per_minute(60));
+        assert_eq!("10".parse::<Quota>().unwrap(), Quota::per_minute(10));
+        assert_eq!("1000, 60".parse::<Quota>().unwrap(), Quota::per_hour(1000));
+
+        for invalid in ["", "0,1", "60,0", "-1,1", "60,1.5", "a,b"] {
+            assert!(
+                matches!(invalid.parse::<Quota>(), Err(Error::InvalidQuota(s)) if s == invalid),
+                "{invalid:?} should not parse"
+            );
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt::{self, Debug, Formatter};
+
+use redis::{aio::ConnectionManager, Script};
+
+use super::{Algorithm, Decision, Error, Quota, RateLimitStore};
+
+/// Counts requests in a string incremented until the quota is reached, expiring with the window.
+///
+/// Returns whether the request is allowed, the count and the elapsed milliseconds.
+const FIXED_WINDOW: &str = r"
+local limit = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local count = tonumber(redis.call('GET', KEYS[1]) or '0')
+local allowed = 0
+if count < limit then
+    count = redis.call('INCR', KEYS[1])
+    if count == 1 then
+        redis.call('PEXPIRE', KEYS[1], period)
+    end
+    allowed = 1
+end
+local ttl = redis.call('PTTL', KEYS[1])
+return {allowed, count, period - math.max(ttl, 0)}
+";
+
+/// Keeps the start of the window and the counts of the current and previous windows in a hash.
+///
+/// Returns whether the request is allowed, both counts and the elapsed milliseconds.
+const SLIDING_WINDOW: &str = r"
+local limit = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = time[1] * 1000 + math.floor(time[2] / 1000)
+local state = redis.call('HMGET', KEYS[1], 'start', 'current', 'previous')
+local start = tonumber(state[1]) or now
+local current = tonumber(state[2]) or 0
+local previous = tonumber(state[3]) or 0
+local windows = math.floor((now - start) / period)
+if windows == 1 then
+    previous = current
+    current = 0
+elseif windows > 1 then
+    previous = 0
+    current = 0
+end
+start = start + windows * period
+local elapsed = now - start
+local allowed = 0
+if previous * (period - elapsed) + (current + 1) * period <= limit * period then
+    current = current + 1
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'start', start, 'current', current, 'previous', previous)
+redis.call('PEXPIRE', KEYS[1], 2 * period - elapsed)
+return {allowed, current, previous, elapsed}
+";
+
+/// Keeps the tokens left and when they were counted in a hash, expiring once the bucket is full.
+///
+/// Returns whether the request is allowed and the thousandths of tokens left.
+const TOKEN_BUCKET: &str = r"
+local limit = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = time[1] * 1000 + math.floor(time[2] / 1000)
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(state[1]) or limit
+local updated = tonumber(state[2]) or now
+tokens = math.min(limit, tokens + (now - updated) * limit / period)
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil((limit - tokens) * period / limit) + 1)
+return {allowed, math.floor(tokens * 1000)}
+";
+
+/// A store keeping the counts in Redis, shared by every process using the same server.
+///
+/// Each key is updated by a script, so concurrent requests are counted atomically, using the clock
+/// of the Redis server.
+#[derive(Clone)]
+pub struct RedisStore {
+    connection: ConnectionManager,
+    prefix: String,
+    fixed_window: Script,
+    sliding_window: Script,
+    token_bucket: Script,
+}
+
+impl RedisStore {
+    /// Creates a store whose keys are prefixed with `rate_limit:`.
+    pub fn new(connection: ConnectionManager) -> Self {
+        Self {
+            connection,
+            prefix: "rate_limit:".to_owned(),
+            fixed_window: Script::new(FIXED_WINDOW),
+            sliding_window: Script::new(SLIDING_WINDOW),
+            token_bucket: Script::new(TOKEN_BUCKET),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+}
+
+impl Debug for RedisStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RateLimitStore for RedisStore {
+    async fn hit(&self, key: &str, quota: &Quota) -> Result<Decision, Error> {
+        let mut connection = self.connection.clone();
+        let script = match quota.algorithm() {
+            Algorithm::FixedWindow => &self.fixed_window,
+            Algorithm::SlidingWindow => &self.sliding_window,
+            Algorithm::TokenBucket => &self.token_bucket,
+        };
+        let reply: Vec<u64> = script
+            .key(format!("{}{key}", self.prefix))
+            .arg(quota.limit())
+            .arg(quota.period_millis())
+            .invoke_async(&mut connection)
+            .await?;
+
+        let value = |index: usize| reply.get(index).copied().unwrap_or_default();
+        let allowed = value(0) == 1;
+        let decision = match quota.algorithm() {
+            Algorithm::FixedWindow => super::fixed_window(quota, value(1), value(2), allowed),
+            Algorithm::SlidingWindow => {
+                super::sliding_window(quota, value(1), value(2), value(3), allowed)
+            }
+            Algorithm::TokenBucket => super::token_bucket(quota, value(1) as f64 / 1000.0, allowed),
+        };
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::*;
+
+    /// Connects to the server at `REDIS_URL`, with keys prefixed to this run.
+    async fn store() -> RedisStore {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL must point to a Redis server");
+        let client = redis::Client::open(url).unwrap();
+        let connection = ConnectionManager::new(client).await.unwrap();
+        let run = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        RedisStore::new(connection).with_prefix(format!("rate_limit:test:{}:", run.as_nanos()))
+    }
+
+    async fn hits(store: &RedisStore, key: &str, quota: &Quota, count: usize) -> Vec<bool> {
+        let mut allowed = vec![];
+        for _ in 0..count {
+            allowed.push(store.hit(key, quota).await.unwrap().is_allowed());
+        }
+        allowed
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a Redis server at REDIS_URL"]
+    async fn test_fixed_window() {
+        let store = store().await;
+        let quota = Quota::new(2, Duration::from_secs(10));
+
+        assert_eq!(hits(&store, "key", &quota, 3).await, [true, true, false]);
+        let decision = store.hit("key", &quota).await.unwrap();
+        assert_eq!(decision.remaining(), 0);
+        let retry_after = decision.retry_after().unwrap();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(10));
+
+        assert!(store.hit("other", &quota).await.unwrap().is_allowed());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a Redis server at REDIS_URL"]
+    async fn test_sliding_window() {
+        let store = store().await;
+        let quota = Quota::new(2, Duration::from_secs(10)).with_algorithm(Algorithm::SlidingWindow);
+
+        assert_eq!(hits(&store, "key", &quota, 3).await, [true, true, false]);
+        let decision = store.hit("key", &quota).await.unwrap();
+        assert_eq!(decision.remaining(), 0);
+        let retry_after = decision.retry_after().unwrap();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(20));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a Redis server at REDIS_URL"]
+    async fn test_token_bucket() {
+        let store = store().await;
+        let quota = Quota::new(2, Duration::from_secs(10)).with_algorithm(Algorithm::TokenBucket);
+
+        let decision = store.hit("key", &quota).await.unwrap();
+        assert_eq!(decision.remaining(), 1);
+        assert_eq!(hits(&store, "key", &quota, 2).await, [true, false]);
+
+        // A token is back every 5 seconds.
+        let decision = store.hit("key", &quota).await.unwrap();
+        let retry_after = decision.retry_after().unwrap();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,599 @@
+//! A server runner handling the signals sent by a service manager.
+//!
+//! `SIGTERM` and `SIGINT` stop accepting connections and drain the open ones, `SIGHUP` runs the
+//! reload hook. The service manager is notified of each state change, see [`notify`](crate::notify).
+use std::{
+    fmt,
+    future::{self, Future},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    Router,
+};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    signal::unix::{signal, Signal, SignalKind},
+    sync::watch,
+    task::JoinSet,
+    time::{self, Interval, MissedTickBehavior},
+};
+use tower_service::Service;
+
+#[cfg(feature = "tls")]
+use crate::tls::TlsAcceptor;
+use crate::{
+    ip::{ClientInfo, TrustedProxiesHandle},
+    listener::{SocketListener, SocketStream},
+    notify, proxy_protocol,
+};
+
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a trusted proxy may take to send the PROXY protocol header.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves a [`Router`] until it receives `SIGTERM` or `SIGINT`.
+///
+/// Each request carries the `ConnectInfo<ClientInfo>` of its connection, as expected by the
+/// [`TrustedProxyLayer`](crate::middleware::layer::TrustedProxyLayer). Connections over a Unix
+/// socket come from a local proxy, so their peer is the loopback address.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use cortev_http::{listener::{ListenFds, SocketListener}, server::Server};
+/// # async fn run(mut fds: ListenFds, router: axum::Router) -> std::io::Result<()> {
+/// // `fds` comes from `ListenFds::from_env`, called in `main` before the runtime starts.
+/// let listener = SocketListener::named(&mut fds, "http", &"127.0.0.1:8080".into())
+///     .await
+///     .unwrap();
+///
+/// Server::new()
+///     .with_shutdown_timeout(Duration::from_secs(10))
+///     .on_reload(|| println!("reloading"))
+///     .serve(listener, router)
+///     .await
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Server {
+    shutdown_timeout: Duration,
+    reload: Option<Arc<dyn Fn() + Send + Sync>>,
+    proxy_protocol: Option<TrustedProxiesHandle>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self {
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            reload: None,
+            proxy_protocol: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Reads a PROXY protocol header on connections from `proxies`, and uses the source address
+    /// it carries as the `ClientInfo` of the connection.
+    ///
+    /// Connections from other peers are served as is. A trusted proxy must send the header,
+    /// connections where it is missing or invalid are closed.
+    #[must_use]
+    pub fn with_proxy_protocol(mut self, proxies: TrustedProxiesHandle) -> Self {
+        self.proxy_protocol = Some(proxies);
+        self
+    }
+
+    /// Serves over TLS, the certificates are reloaded on `SIGHUP` before the reload hook runs.
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub fn with_tls(mut self, tls: TlsAcceptor) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets how long open connections may take to finish once shutdown starts, 30 seconds by
+    /// default. Connections still open afterwards are closed.
+    ///
+    /// Keep it below the `TimeoutStopSec` of the service.
+    #[must_use]
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Sets the hook called on `SIGHUP`, for example to reload the trusted proxies.
+    #[must_use]
+    pub fn on_reload<F>(mut self, reload: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.reload = Some(Arc::new(reload));
+        self
+    }
+
+    /// Serves `router` on `listener` until `SIGTERM` or `SIGINT`, then drains the connections.
+    ///
+    /// # Errors
+    /// Returns an error if the signal handlers cannot be installed.
+    pub async fn serve(self, listener: SocketListener, router: Router) -> io::Result<()> {
+        self.serve_with_shutdown(listener, router, future::pending())
+            .await
+    }
+
+    /// Like [`serve`](Self::serve), but also shuts down when `shutdown` completes.
+    ///
+    /// Failing to notify the service manager once serving is logged, and does not stop the
+    /// server.
+    ///
+    /// # Errors
+    /// Returns an error if the signal handlers cannot be installed, or if the service manager
+    /// cannot be told that the server is ready.
+    pub async fn serve_with_shutdown<F>(
+        self,
+        listener: SocketListener,
+        router: Router,
+        shutdown: F,
+    ) -> io::Result<()>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        let mut signals = Signals::new()?;
+        let mut watchdog = notify::watchdog_interval().map(|period| {
+            let mut interval = time::interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+        let (drain, draining) = watch::channel(());
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+
+        notify::ready()?;
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        let connection = self.accept(stream, peer, router.clone(), draining.clone());
+                        connections.spawn(connection);
+                    }
+                    Err(error) if is_connection_error(&error) => {}
+                    // Most likely out of file descriptors, give connections time to close.
+                    Err(_) => time::sleep(Duration::from_secs(1)).await,
+                },
+                () = &mut shutdown => break,
+                signal = signals.recv() => match signal {
+                    Action::Shutdown => break,
+                    Action::Reload => self.reload(),
+                },
+                () = tick(watchdog.as_mut()) => log_notify_error(notify::watchdog()),
+                // Finished connections are collected as they go.
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            }
+        }
+
+        log_notify_error(notify::stopping());
+        drop(listener);
+
+        drain.send_replace(());
+        let drained = async { while connections.join_next().await.is_some() {} };
+        if time::timeout(self.shutdown_timeout, drained).await.is_err() {
+            connections.shutdown().await;
+        }
+        Ok(())
+    }
+
+    fn accept(
+        &self,
+        stream: SocketStream,
+        peer: Option<SocketAddr>,
+        router: Router,
+        draining: watch::Receiver<()>,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        // Connections over a Unix socket come from a local proxy.
+        let peer = peer.map_or(IpAddr::V4(Ipv4Addr::LOCALHOST), |peer| peer.ip());
+        let proxy_protocol = self
+            .proxy_protocol
+            .as_ref()
+            .is_some_and(|proxies| proxies.current().is_trusted(&peer));
+        #[cfg(feature = "tls")]
+        let tls = self.tls.clone();
+
+        // The connection is set up in its own task so a slow client does not hold the accept
+        // loop, and failures only close this connection.
+        async move {
+            let mut stream = stream;
+            let mut peer = peer;
+
+            if proxy_protocol {
+                let header = time::timeout(
+                    PROXY_HEADER_TIMEOUT,
+                    proxy_protocol::read_header(&mut stream),
+                );
+                match header.await {
+                    Ok(Ok(source)) => peer = source.map_or(peer, |source| source.ip()),
+                    _ => return,
+                }
+            }
+
+            #[cfg(feature = "tls")]
+            if let Some(tls) = tls {
+                if let Ok(stream) = tls.accept(stream).await {
+                    serve_connection(stream, peer, router, draining).await;
+                }
+                return;
+            }
+
+            serve_connection(stream, peer, router, draining).await;
+        }
+    }
+
+    fn reload(&self) {
+        log_notify_error(notify::reloading());
+        // Invalid certificates keep the current ones, the service must not stop over them.
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            tls.reload_or_log();
+        }
+        if let Some(reload) = &self.reload {
+            reload();
+        }
+        log_notify_error(notify::ready());
+    }
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("shutdown_timeout", &self.shutdown_timeout)
+            .field("reload", &self.reload.is_some())
+            .field("proxy_protocol", &self.proxy_protocol)
+            .finish_non_exhaustive()
+    }
+}
+
+async fn serve_connection<I>(
+    stream: I,
+    peer: IpAddr,
+    router: Router,
+    mut draining: watch::Receiver<()>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let info = ClientInfo::new(peer);
+    let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(info));
+        router.clone().call(request)
+    });
+
+    // HTTP/2 is detected from the connection preface, or negotiated through ALPN over TLS.
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+    tokio::pin!(connection);
+
+    // Errors only concern this connection, such as a client hanging up.
+    tokio::select! {
+        _ = connection.as_mut() => return,
+        _ = draining.changed() => {}
+    }
+
+    // Finish the request in flight, then close the connection.
+    connection.as_mut().graceful_shutdown();
+    let _ = connection.await;
+}
+
+/// Logs a failed notification, the service manager only misses this state change.
+fn log_notify_error(result: io::Result<bool>) {
+    #[cfg(feature = "tracing")]
+    if let Err(error) = result {
+        tracing::warn!(%error, "failed to notify the service manager");
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = result;
+}
+
+/// Errors caused by a single connection, which must not stop the accept loop.
+fn is_connection_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+async fn tick(interval: Option<&mut Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+struct Signals {
+    terminate: Signal,
+    interrupt: Signal,
+    hangup: Signal,
+}
+
+impl Signals {
+    fn new() -> io::Result<Self> {
+        Ok(Self {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+            hangup: signal(SignalKind::hangup())?,
+        })
+    }
+
+    async fn recv(&mut self) -> Action {
+        tokio::select! {
+            _ = self.terminate.recv() => Action::Shutdown,
+            _ = self.interrupt.recv() => Action::Shutdown,
+            _ = self.hangup.recv() => Action::Reload,
+        }
+    }
+}
+
+enum Action {
+    Shutdown,
+    Reload,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::routing;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::oneshot,
+    };
+
+    use super::*;
+    use crate::ip::TrustedProxies;
+
+    #[tokio::test]
+    async fn test_shutdown_drains_connections() {
+        let router = Router::new().route(
+            "/",
+            routing::get(|ConnectInfo(info): ConnectInfo<ClientInfo>| async move {
+                time::sleep(Duration::from_millis(100)).await;
+                info.ip().to_string()
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+
+        let server = tokio::spawn(
+            Server::new()
+                .with_shutdown_timeout(Duration::from_secs(5))
+                .serve_with_shutdown(listener.into(), router, async {
+                    let _ = shutdown_rx.await;
+                }),
+        );
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(20)).await;
+        shutdown.send(()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("127.0.0.1"));
+
+        server.await.unwrap().unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout_closes_connections() {
+        let router = Router::new().route("/", routing::get(future::pending::<()>));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(
+            Server::new()
+                .with_shutdown_timeout(Duration::from_millis(50))
+                .serve_with_shutdown(listener.into(), router, async {
+                    let _ = shutdown_rx.await;
+                }),
+        );
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(20)).await;
+        shutdown.send(()).unwrap();
+        server.await.unwrap().unwrap();
+
+        // The request never finishes, so the connection is closed without a response.
+        let mut response = String::new();
+        let read = time::timeout(Duration::from_secs(1), stream.read_to_string(&mut response));
+        assert!(read.await.is_ok());
+        assert!(response.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_http2_prior_knowledge() {
+        let router = Router::new().route("/", routing::get(|| async { "Hello!" }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server =
+            tokio::spawn(
+                Server::new().serve_with_shutdown(listener.into(), router, async {
+                    let _ = shutdown_rx.await;
+                }),
+            );
+
+        // The connection preface followed by an empty SETTINGS frame.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+            .await
+            .unwrap();
+
+        // The server answers with its own SETTINGS frame.
+        let mut frame = [0; 9];
+        stream.read_exact(&mut frame).await.unwrap();
+        assert_eq!(frame[3], 0x04);
+        drop(stream);
+
+        shutdown.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol() {
+        let router = Router::new().route(
+            "/",
+            routing::get(|ConnectInfo(info): ConnectInfo<ClientInfo>| async move {
+                info.ip().to_string()
+            }),
+        );
+        let proxies = TrustedProxies::new().trust("127.0.0.1/32".parse().unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(
+            Server::new()
+                .with_proxy_protocol(TrustedProxiesHandle::new(proxies))
+                .serve_with_shutdown(listener.into(), router, async {
+                    let _ = shutdown_rx.await;
+                }),
+        );
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 56324 80\r\nGET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.ends_with("203.0.113.7"));
+
+        // A trusted proxy must send the header, the connection is closed or reset without it.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).await;
+        assert!(response.is_empty());
+
+        shutdown.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn test_tls() {
+        use std::{path::Path, sync::Arc};
+
+        use tokio_rustls::{
+            rustls::{self, crypto::ring, pki_types::ServerName},
+            TlsConnector,
+        };
+
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+        let tls = TlsAcceptor::builder()
+            .with_certificate(
+                fixtures.join("localhost.pem"),
+                fixtures.join("localhost.key"),
+            )
+            .with_sni_certificate(
+                ["api.localhost"],
+                fixtures.join("api.pem"),
+                fixtures.join("api.key"),
+            )
+            .build()
+            .unwrap();
+
+        let router = Router::new().route(
+            "/",
+            routing::get(|ConnectInfo(info): ConnectInfo<ClientInfo>| async move {
+                info.ip().to_string()
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(Server::new().with_tls(tls).serve_with_shutdown(
+            listener.into(),
+            router,
+            async {
+                let _ = shutdown_rx.await;
+            },
+        ));
+
+        let mut roots = rustls::RootCertStore::empty();
+        let ca = std::fs::read(fixtures.join("ca.pem")).unwrap();
+        for cert in rustls_pemfile::certs(&mut ca.as_slice()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let config =
+            rustls::ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        let config = Arc::new(config);
+        let connector = TlsConnector::from(config.clone());
+
+        // The certificate of each name only verifies if the server picked it by SNI.
+        for name in ["localhost", "api.localhost"] {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let server_name = ServerName::try_from(name).unwrap();
+            let mut stream = connector.connect(server_name, stream).await.unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK"));
+            assert!(response.ends_with("127.0.0.1"));
+        }
+
+        // Clients supporting HTTP/2 negotiate it through ALPN.
+        let mut config = (*config).clone();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let connector = TlsConnector::from(Arc::new(config));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let stream = connector.connect(server_name, stream).await.unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        drop(stream);
+
+        shutdown.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
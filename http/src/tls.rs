@@ -0,0 +1,416 @@
+//! TLS termination with certificates loaded from PEM files.
+//!
+//! Certificates are chosen by the server name (SNI) sent by the client, and can be reloaded
+//! without dropping connections: established connections keep their session, new handshakes use
+//! the new certificates.
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time,
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    server::TlsStream,
+};
+
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts TLS connections, see [`Server::with_tls`](crate::server::Server::with_tls).
+///
+/// ```no_run
+/// # use cortev_http::tls::TlsAcceptor;
+/// let tls = TlsAcceptor::builder()
+///     .with_certificate("/etc/ssl/example.pem", "/etc/ssl/example.key")
+///     .with_sni_certificate(["api.example.com"], "/etc/ssl/api.pem", "/etc/ssl/api.key")
+///     .build()?;
+/// # Ok::<_, cortev_http::tls::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: tokio_rustls::TlsAcceptor,
+    resolver: Arc<CertResolver>,
+    handshake_timeout: Duration,
+}
+
+impl TlsAcceptor {
+    pub fn builder() -> TlsAcceptorBuilder {
+        TlsAcceptorBuilder::default()
+    }
+
+    /// Reads the certificates and keys again, for example on `SIGHUP`.
+    ///
+    /// # Errors
+    /// Returns an error if a file cannot be read or is invalid, the current certificates are
+    /// kept.
+    pub fn reload(&self) -> Result<(), Error> {
+        self.resolver.reload()
+    }
+
+    /// Reloads the certificates whenever their files change, checking every `period`.
+    ///
+    /// The future never completes and is meant to be spawned. Files that fail to load, such as
+    /// a certificate written before its key, are retried at the next check while the current
+    /// certificates stay in use.
+    pub async fn watch(self, period: Duration) {
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if self.resolver.changed() {
+                self.reload_or_log();
+            }
+        }
+    }
+
+    /// Reloads the certificates, logging the error and keeping the current ones if it fails.
+    pub(crate) fn reload_or_log(&self) {
+        let result = self.reload();
+        #[cfg(feature = "tracing")]
+        if let Err(error) = result {
+            tracing::warn!(
+                error = &error as &dyn std::error::Error,
+                "failed to reload the TLS certificates"
+            );
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = result;
+    }
+
+    /// Performs the TLS handshake on `stream`.
+    ///
+    /// # Errors
+    /// Returns an error if the handshake fails or takes longer than the handshake timeout.
+    pub async fn accept<S>(&self, stream: S) -> io::Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        time::timeout(self.handshake_timeout, self.acceptor.accept(stream))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))?
+    }
+}
+
+impl fmt::Debug for TlsAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsAcceptor")
+            .field("resolver", &self.resolver)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TlsAcceptorBuilder {
+    sources: Vec<CertSource>,
+    handshake_timeout: Option<Duration>,
+}
+
+impl TlsAcceptorBuilder {
+    /// Sets the certificate used when the client sends no server name, or one matching no other
+    /// certificate.
+    #[must_use]
+    pub fn with_certificate<C, K>(mut self, cert: C, key: K) -> Self
+    where
+        C: Into<PathBuf>,
+        K: Into<PathBuf>,
+    {
+        self.sources.push(CertSource {
+            names: vec![],
+            cert: cert.into(),
+            key: key.into(),
+        });
+        self
+    }
+
+    /// Adds a certificate for the server names `names`, such as `api.example.com` or
+    /// `*.example.com`.
+    #[must_use]
+    pub fn with_sni_certificate<N, C, K>(mut self, names: N, cert: C, key: K) -> Self
+    where
+        N: IntoIterator,
+        N::Item: Into<String>,
+        C: Into<PathBuf>,
+        K: Into<PathBuf>,
+    {
+        self.sources.push(CertSource {
+            names: names
+                .into_iter()
+                .map(|name| name.into().to_ascii_lowercase())
+                .collect(),
+            cert: cert.into(),
+            key: key.into(),
+        });
+        self
+    }
+
+    /// Sets how long a client may take to complete the handshake, 10 seconds by default.
+    #[must_use]
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Loads the certificates.
+    ///
+    /// # Errors
+    /// Returns an error if a file cannot be read or is invalid.
+    pub fn build(self) -> Result<TlsAcceptor, Error> {
+        let loaded = LoadedCerts::load(&self.sources)?;
+        let resolver = Arc::new(CertResolver {
+            sources: self.sources,
+            loaded: RwLock::new(Arc::new(loaded)),
+        });
+
+        let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(TlsAcceptor {
+            acceptor: Arc::new(config).into(),
+            resolver,
+            handshake_timeout: self.handshake_timeout.unwrap_or(DEFAULT_HANDSHAKE_TIMEOUT),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct CertSource {
+    names: Vec<String>,
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl CertSource {
+    fn modified(&self) -> [Option<SystemTime>; 2] {
+        [&self.cert, &self.key].map(|path| {
+            std::fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .ok()
+        })
+    }
+}
+
+#[derive(Debug)]
+struct CertResolver {
+    sources: Vec<CertSource>,
+    loaded: RwLock<Arc<LoadedCerts>>,
+}
+
+impl CertResolver {
+    fn current(&self) -> Arc<LoadedCerts> {
+        self.loaded
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn reload(&self) -> Result<(), Error> {
+        let loaded = LoadedCerts::load(&self.sources)?;
+        *self.loaded.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(loaded);
+        Ok(())
+    }
+
+    fn changed(&self) -> bool {
+        let current = self.current();
+        self.sources
+            .iter()
+            .zip(&current.modified)
+            .any(|(source, modified)| source.modified() != *modified)
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let loaded = self.current();
+        client_hello
+            .server_name()
+            .and_then(|name| loaded.find(name))
+            .or_else(|| loaded.default.clone())
+    }
+}
+
+#[derive(Debug)]
+struct LoadedCerts {
+    default: Option<Arc<CertifiedKey>>,
+    by_name: Vec<(String, Arc<CertifiedKey>)>,
+    modified: Vec<[Option<SystemTime>; 2]>,
+}
+
+impl LoadedCerts {
+    fn load(sources: &[CertSource]) -> Result<Self, Error> {
+        let mut loaded = Self {
+            default: None,
+            by_name: vec![],
+            modified: vec![],
+        };
+
+        for source in sources {
+            // Read the times first, so a file changed while loading is loaded again.
+            loaded.modified.push(source.modified());
+            let key = Arc::new(load_certified_key(&source.cert, &source.key)?);
+
+            if source.names.is_empty() {
+                loaded.default = Some(key);
+            } else {
+                let names = source.names.iter().cloned();
+                loaded.by_name.extend(names.map(|name| (name, key.clone())));
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    /// Finds the certificate for `server_name`, exact names first, then wildcards.
+    fn find(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        let server_name = server_name.to_ascii_lowercase();
+        let wildcard = server_name
+            .split_once('.')
+            .map(|(_, parent)| format!("*.{parent}"));
+
+        let find = |name: &str| {
+            self.by_name
+                .iter()
+                .find(|(candidate, _)| candidate == name)
+                .map(|(_, key)| key.clone())
+        };
+        find(&server_name).or_else(|| wildcard.as_deref().and_then(find))
+    }
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> Result<CertifiedKey, Error> {
+    let certs = rustls_pemfile::certs(&mut read(cert)?.as_slice())
+        .collect::<Result<Vec<CertificateDer<'static>>, _>>()
+        .map_err(|source| Error::Io {
+            path: cert.to_owned(),
+            source,
+        })?;
+    if certs.is_empty() {
+        return Err(Error::NoCertificate(cert.to_owned()));
+    }
+
+    let key_der: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut read(key)?.as_slice())
+        .map_err(|source| Error::Io {
+            path: key.to_owned(),
+            source,
+        })?
+        .ok_or_else(|| Error::NoPrivateKey(key.to_owned()))?;
+
+    let signing_key = ring::sign::any_supported_type(&key_der)?;
+    let certified = CertifiedKey::new(certs, signing_key);
+    certified.keys_match()?;
+    Ok(certified)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|source| Error::Io {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to read {path:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{0:?} contains no certificate")]
+    NoCertificate(PathBuf),
+
+    #[error("{0:?} contains no private key")]
+    NoPrivateKey(PathBuf),
+
+    #[error("invalid TLS configuration")]
+    Rustls(#[from] tokio_rustls::rustls::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tls")
+            .join(name)
+    }
+
+    fn builder() -> TlsAcceptorBuilder {
+        TlsAcceptor::builder()
+            .with_certificate(fixture("localhost.pem"), fixture("localhost.key"))
+            .with_sni_certificate(
+                ["api.localhost", "*.api.localhost"],
+                fixture("api.pem"),
+                fixture("api.key"),
+            )
+    }
+
+    #[test]
+    fn test_sni() {
+        let tls = builder().build().unwrap();
+        let loaded = tls.resolver.current();
+        let default = loaded.default.clone().unwrap();
+        let api = loaded.find("API.localhost").unwrap();
+
+        assert!(!Arc::ptr_eq(&default, &api));
+        assert!(Arc::ptr_eq(&loaded.find("v1.api.localhost").unwrap(), &api));
+        assert!(loaded.find("other.localhost").is_none());
+    }
+
+    #[test]
+    fn test_reload_keeps_certificates_on_error() {
+        let dir = std::env::temp_dir().join(format!("tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::copy(fixture("localhost.pem"), &cert).unwrap();
+        std::fs::copy(fixture("localhost.key"), &key).unwrap();
+
+        let tls = TlsAcceptor::builder()
+            .with_certificate(&cert, &key)
+            .build()
+            .unwrap();
+        let before = tls.resolver.current().default.clone().unwrap();
+
+        std::fs::copy(fixture("api.pem"), &cert).unwrap();
+        assert!(matches!(tls.reload(), Err(Error::Rustls(_))));
+        assert!(Arc::ptr_eq(
+            &tls.resolver.current().default.clone().unwrap(),
+            &before
+        ));
+
+        std::fs::copy(fixture("api.key"), &key).unwrap();
+        tls.reload().unwrap();
+        assert!(!Arc::ptr_eq(
+            &tls.resolver.current().default.clone().unwrap(),
+            &before
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let result = TlsAcceptor::builder()
+            .with_certificate(fixture("localhost.pem"), fixture("localhost.pem"))
+            .build();
+        assert!(matches!(result, Err(Error::NoPrivateKey(_))));
+    }
+}
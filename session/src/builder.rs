@@ -53,6 +53,7 @@ impl SessionBuilder<WithData> {
             key: self.key,
             data: self.data.unwrap(),
             state: SessionState::Unchanged,
+            name: std::marker::PhantomData,
         }
     }
 }
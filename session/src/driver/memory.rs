@@ -1,4 +1,7 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 #[cfg(feature = "memory")]
 use dashmap::DashMap;
@@ -7,9 +10,22 @@ use crate::{builder::BuildSession, key::SessionKey, Session};
 
 use super::{SessionData, SessionDriver, SessionResult};
 
+/// A session stored in memory along with the instant it expires at.
+#[derive(Debug, Clone)]
+struct MemoryEntry {
+    session: Session,
+    expires_at: Instant,
+}
+
+impl MemoryEntry {
+    fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MemoryDriver {
-    sessions: Arc<DashMap<SessionKey, Session>>,
+    sessions: Arc<DashMap<SessionKey, MemoryEntry>>,
     ttl: Duration,
 }
 
@@ -17,28 +33,49 @@ impl MemoryDriver {
     pub fn new() -> Self {
         Self::default()
     }
-}
 
-impl Default for MemoryDriver {
-    fn default() -> Self {
+    /// Creates a new `MemoryDriver` where sessions expire after the given TTL.
+    ///
+    /// A short TTL makes the driver suitable as an in-process cache in front of a remote driver,
+    /// see [`TieredDriver`](super::TieredDriver).
+    pub fn with_ttl(ttl: Duration) -> Self {
         Self {
             sessions: Arc::new(DashMap::new()),
-            ttl: Duration::from_secs(120 * 60),
+            ttl,
         }
     }
 }
 
+impl Default for MemoryDriver {
+    fn default() -> Self {
+        Self::with_ttl(Duration::from_secs(120 * 60))
+    }
+}
+
 impl SessionDriver for MemoryDriver {
     async fn read(&self, key: SessionKey) -> SessionResult<Option<Session>> {
-        let session = self.sessions.get(&key);
-        let session = session.map(|session| session.value().to_owned());
-        Ok(session)
+        let entry = self
+            .sessions
+            .get(&key)
+            .map(|entry| entry.value().to_owned());
+        match entry {
+            Some(entry) if entry.is_expired() => {
+                self.sessions.remove(&key);
+                Ok(None)
+            }
+            Some(entry) => Ok(Some(entry.session)),
+            None => Ok(None),
+        }
     }
 
     async fn write(&self, key: SessionKey, data: SessionData) -> SessionResult<SessionKey> {
         let session = Session::builder(key.clone()).with_data(data).build();
+        let entry = MemoryEntry {
+            session,
+            expires_at: Instant::now() + self.ttl,
+        };
 
-        self.sessions.insert(key.clone(), session);
+        self.sessions.insert(key.clone(), entry);
         Ok(key)
     }
 
@@ -53,17 +53,19 @@ mod null;
 
 #[cfg(feature = "redis")]
 mod redis;
+mod tiered;
 
 // Drivers
 #[cfg(feature = "memory")]
 pub use memory::MemoryDriver;
 
 #[cfg(feature = "redis")]
-pub use redis::{RedisConnectionKind, RedisDriver};
+pub use redis::{KeyspaceInvalidator, RedisConnectionKind, RedisDriver};
 
 pub use null::NullDriver;
+pub use tiered::TieredDriver;
 
-type SessionResult<T> = Result<T, SessionError>;
+pub(crate) type SessionResult<T> = Result<T, SessionError>;
 
 pub trait SessionDriver: Sync {
     fn read(&self, key: SessionKey) -> impl Future<Output = SessionResult<Option<Session>>> + Send;
@@ -88,9 +90,20 @@ pub trait SessionDriver: Sync {
         &self,
         key: SessionKey,
         data: SessionData,
+    ) -> impl Future<Output = SessionResult<SessionKey>> + Send {
+        let new_key = generate_session_key();
+        self.regenerate_to(key, new_key.into(), data)
+    }
+
+    /// Moves the session data from `key` to the already generated `new_key`.
+    fn regenerate_to(
+        &self,
+        key: SessionKey,
+        new_key: SessionKey,
+        data: SessionData,
     ) -> impl Future<Output = SessionResult<SessionKey>> + Send {
         async move {
-            let session_key = self.create(data).await?;
+            let session_key = self.write(new_key, data).await?;
             self.destroy(key).await?;
             Ok(session_key)
         }
@@ -100,10 +113,21 @@ pub trait SessionDriver: Sync {
         &self,
         key: SessionKey,
         data: SessionData,
+    ) -> impl Future<Output = SessionResult<SessionKey>> + Send {
+        let new_key = generate_session_key();
+        self.invalidate_to(key, new_key.into(), data)
+    }
+
+    /// Destroys the session at `key` and stores `data` under the already generated `new_key`.
+    fn invalidate_to(
+        &self,
+        key: SessionKey,
+        new_key: SessionKey,
+        data: SessionData,
     ) -> impl Future<Output = SessionResult<SessionKey>> + Send {
         async move {
             self.destroy(key).await?;
-            self.create(data).await
+            self.write(new_key, data).await
         }
     }
 }
@@ -1,7 +1,11 @@
-use crate::{builder::BuildSession, key::SessionKey, Session};
+use crate::{key::SessionKey, Session};
 
-use super::{SessionData, SessionDriver, SessionResult};
+use super::{SessionDriver, SessionResult};
 
+/// A driver that does not store anything.
+///
+/// Since nothing is ever stored, no key presented by a client exists and every read misses. The
+/// middleware therefore issues a fresh server generated key on every request.
 #[derive(Debug, Default, Clone)]
 pub struct NullDriver {}
 
@@ -12,11 +16,8 @@ impl NullDriver {
 }
 
 impl SessionDriver for NullDriver {
-    async fn read(&self, key: SessionKey) -> SessionResult<Option<Session>> {
-        let session = Session::builder(key)
-            .with_data(SessionData::default())
-            .build();
-        Ok(Some(session))
+    async fn read(&self, _key: SessionKey) -> SessionResult<Option<Session>> {
+        Ok(None)
     }
 
     async fn write(&self, key: SessionKey, _data: super::SessionData) -> SessionResult<SessionKey> {
@@ -7,6 +7,7 @@ use std::{
 
 #[cfg(feature = "redis-pool")]
 use deadpool_redis::Pool;
+use futures_util::StreamExt;
 use redis::{
     aio::{ConnectionLike, ConnectionManager},
     cmd, FromRedisValue, RedisError,
@@ -17,7 +18,7 @@ use crate::{
     SessionKey,
 };
 
-use super::{generate_session_key, FromJson, SessionDriver, SessionResult, ToJson};
+use super::{FromJson, SessionDriver, SessionResult, ToJson};
 
 /// Represents the kind of Redis connection being used.
 ///
@@ -354,15 +355,16 @@ impl SessionDriver for RedisDriver {
 
     /// Regenerates a session by replacing its key while preserving its data.
     ///
-    /// The old session key is deleted, and a new session key is generated and associated
-    /// with the session data.
+    /// The old session key is deleted, and the session data is associated with `new_key` in a
+    /// single pipeline.
     ///
     /// # Errors
     /// Returns a `SessionError` if updating Redis fails or if the session data cannot be serialized.
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self, data)))]
-    async fn regenerate(
+    async fn regenerate_to(
         &self,
         old_key: SessionKey,
+        new_key: SessionKey,
         data: SessionData,
     ) -> SessionResult<SessionKey> {
         #[cfg(feature = "tracing")]
@@ -370,7 +372,6 @@ impl SessionDriver for RedisDriver {
         let old_prefixed_key = self.prefixed_key(&old_key);
 
         let data = data.to_json()?;
-        let new_key = generate_session_key();
         let prefixed_new_key = self.prefixed_key(&new_key);
         let mut pipeline = redis::pipe();
         pipeline.set_ex(&prefixed_new_key, data, self.ttl.as_secs());
@@ -387,7 +388,7 @@ impl SessionDriver for RedisDriver {
                 kind: SessionErrorKind::Regenerate,
             })?;
 
-        let session_key = SessionKey::from(new_key);
+        let session_key = new_key;
 
         #[cfg(feature = "tracing")]
         tracing::info!("Session regenerated successfully to {:?}", session_key);
@@ -397,20 +398,24 @@ impl SessionDriver for RedisDriver {
 
     /// Invalidates a session by replacing its key and deleting the old session data.
     ///
-    /// The old session key is deleted, and a new session key is generated and associated
-    /// with the session data.
+    /// The old session key is deleted, and the session data is associated with `new_key` in a
+    /// single pipeline.
     ///
     /// # Errors
     /// Returns a `SessionError` if updating Redis fails or if the session data cannot be serialized.
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self, data)))]
-    async fn invalidate(&self, key: SessionKey, data: SessionData) -> SessionResult<SessionKey> {
+    async fn invalidate_to(
+        &self,
+        key: SessionKey,
+        new_key: SessionKey,
+        data: SessionData,
+    ) -> SessionResult<SessionKey> {
         #[cfg(feature = "tracing")]
         tracing::debug!("Invalidating session...");
 
         let prefixed_key = self.prefixed_key(&key);
 
         let data = data.to_json()?;
-        let new_key = generate_session_key();
         let prefixed_new_key = self.prefixed_key(&new_key);
         let mut pipeline = redis::pipe();
         pipeline.del(&prefixed_key);
@@ -427,7 +432,7 @@ impl SessionDriver for RedisDriver {
                 kind: SessionErrorKind::Invalidate,
             })?;
 
-        let session_key = SessionKey::from(new_key);
+        let session_key = new_key;
 
         #[cfg(feature = "tracing")]
         tracing::info!("Session invalidated successfully to {:?}", session_key);
@@ -441,6 +446,113 @@ impl SessionDriver for RedisDriver {
     }
 }
 
+/// Evicts sessions from a cache driver when their Redis key changes on any instance.
+///
+/// The invalidator subscribes to Redis keyspace notifications for the session keys and destroys
+/// the matching entry in the cache whenever a key is written, deleted or expires. This keeps the
+/// cache of a [`TieredDriver`](super::TieredDriver) coherent across instances.
+///
+/// Keyspace notifications are disabled by default and must be enabled on the server, for example
+/// with `CONFIG SET notify-keyspace-events Kgx$`. The `expire` events sent when a read refreshes
+/// the TTL of a session are ignored, so reads do not evict the cache.
+#[derive(Debug, Clone)]
+pub struct KeyspaceInvalidator<D> {
+    client: redis::Client,
+    cache: D,
+    prefix: Option<Cow<'static, str>>,
+    database: i64,
+}
+
+impl<D> KeyspaceInvalidator<D>
+where
+    D: SessionDriver,
+{
+    /// Creates a new `KeyspaceInvalidator` listening on `client` and evicting from `cache`.
+    pub fn new(client: redis::Client, cache: D) -> Self {
+        Self {
+            client,
+            cache,
+            prefix: None,
+            database: 0,
+        }
+    }
+
+    /// Sets the session key prefix, it must match the prefix of the `RedisDriver`.
+    pub fn with_prefix(mut self, prefix: impl Into<Cow<'static, str>>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Sets the Redis database the sessions are stored in.
+    pub fn with_database(mut self, database: i64) -> Self {
+        self.database = database;
+        self
+    }
+
+    /// Listens for keyspace notifications until the connection is closed.
+    ///
+    /// This future never completes while the connection is alive, it is meant to be spawned.
+    ///
+    /// # Errors
+    /// Returns a `SessionError` if the subscription fails or if the cache cannot be updated.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn run(self) -> SessionResult<()> {
+        let channel = format!(
+            "__keyspace@{}__:{}",
+            self.database,
+            self.prefix.as_deref().unwrap_or_default()
+        );
+
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub
+            .psubscribe(format!("{}*", escape_pattern(&channel)))
+            .await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::info!("Listening for keyspace notifications on {}*", channel);
+
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let Some(key) = message.get_channel_name().strip_prefix(&channel) else {
+                continue;
+            };
+            // The payload of a keyspace notification is the name of the command or event.
+            let Ok(event) = message.get_payload::<String>() else {
+                continue;
+            };
+            if !is_invalidating_event(&event) {
+                continue;
+            }
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!("Evicting session from the cache");
+
+            self.cache.destroy(SessionKey::from(key)).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns whether a keyspace event changes or removes the session stored in the key.
+///
+/// `expire` is sent by the `GETEX` of every read and leaves the session untouched.
+fn is_invalidating_event(event: &str) -> bool {
+    matches!(event, "set" | "del" | "expired")
+}
+
+/// Escapes the glob metacharacters of `value` for use in a `PSUBSCRIBE` pattern.
+fn escape_pattern(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 impl Debug for RedisConnectionKind {
     /// Provides a debug-friendly string representation of the connection kind.
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -451,3 +563,27 @@ impl Debug for RedisConnectionKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalidating_events() {
+        for event in ["set", "del", "expired"] {
+            assert!(is_invalidating_event(event), "{event}");
+        }
+        for event in ["expire", "persist", "hset"] {
+            assert!(!is_invalidating_event(event), "{event}");
+        }
+    }
+
+    #[test]
+    fn test_escape_pattern() {
+        assert_eq!(
+            escape_pattern("__keyspace@0__:app:session:"),
+            "__keyspace@0__:app:session:"
+        );
+        assert_eq!(escape_pattern(r"s*[1]?\"), r"s\*\[1\]\?\\");
+    }
+}
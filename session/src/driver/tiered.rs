@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use crate::{key::SessionKey, Session};
+
+use super::{SessionData, SessionDriver, SessionResult};
+
+/// A driver that keeps a short-lived cache in front of a slower, authoritative driver.
+///
+/// Reads are served from the cache (`L1`) when possible and fall back to the store (`L2`),
+/// populating the cache on a hit. Every mutation is written through to the store first and then
+/// mirrored in the cache, so the store always remains the source of truth.
+///
+/// The cache is usually a [`MemoryDriver`](super::MemoryDriver) with a TTL of a few seconds,
+/// which bounds how long another instance may serve stale data. When running several instances
+/// against Redis, pair it with a
+/// [`KeyspaceInvalidator`](super::KeyspaceInvalidator) to evict entries as soon as they change.
+#[derive(Debug, Clone)]
+pub struct TieredDriver<L1, L2> {
+    cache: L1,
+    store: L2,
+}
+
+impl<L1, L2> TieredDriver<L1, L2>
+where
+    L1: SessionDriver,
+    L2: SessionDriver,
+{
+    /// Creates a new `TieredDriver` using `cache` in front of `store`.
+    pub fn new(cache: L1, store: L2) -> Self {
+        Self { cache, store }
+    }
+
+    /// Returns the cache driver.
+    pub fn cache(&self) -> &L1 {
+        &self.cache
+    }
+
+    /// Returns the authoritative store driver.
+    pub fn store(&self) -> &L2 {
+        &self.store
+    }
+}
+
+impl<L1, L2> SessionDriver for TieredDriver<L1, L2>
+where
+    L1: SessionDriver + Send,
+    L2: SessionDriver + Send,
+{
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    async fn read(&self, key: SessionKey) -> SessionResult<Option<Session>> {
+        if let Some(session) = self.cache.read(key.clone()).await? {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("Session served from the cache");
+            return Ok(Some(session));
+        }
+
+        let session = self.store.read(key).await?;
+        if let Some(session) = &session {
+            let (key, _, data) = session.clone().into_parts();
+            self.cache.write(key, data).await?;
+        }
+
+        Ok(session)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self, data)))]
+    async fn write(&self, key: SessionKey, data: SessionData) -> SessionResult<SessionKey> {
+        let key = self.store.write(key, data.clone()).await?;
+        self.cache.write(key.clone(), data).await?;
+        Ok(key)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    async fn destroy(&self, key: SessionKey) -> SessionResult<()> {
+        self.cache.destroy(key.clone()).await?;
+        self.store.destroy(key).await
+    }
+
+    fn ttl(&self) -> Duration {
+        self.store.ttl()
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self, data)))]
+    async fn regenerate_to(
+        &self,
+        key: SessionKey,
+        new_key: SessionKey,
+        data: SessionData,
+    ) -> SessionResult<SessionKey> {
+        let new_key = self
+            .store
+            .regenerate_to(key.clone(), new_key, data.clone())
+            .await?;
+        self.cache.destroy(key).await?;
+        self.cache.write(new_key.clone(), data).await?;
+        Ok(new_key)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self, data)))]
+    async fn invalidate_to(
+        &self,
+        key: SessionKey,
+        new_key: SessionKey,
+        data: SessionData,
+    ) -> SessionResult<SessionKey> {
+        let new_key = self
+            .store
+            .invalidate_to(key.clone(), new_key, data.clone())
+            .await?;
+        self.cache.destroy(key).await?;
+        self.cache.write(new_key.clone(), data).await?;
+        Ok(new_key)
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::driver::MemoryDriver;
+
+    fn data() -> SessionData {
+        let mut data = SessionData::new();
+        data.insert("name".into(), Value::String("John".into()));
+        data
+    }
+
+    #[tokio::test]
+    async fn test_read_populates_cache() {
+        let driver = TieredDriver::new(MemoryDriver::new(), MemoryDriver::new());
+        let key = driver.store().create(data()).await.unwrap();
+
+        assert!(driver.cache().read(key.clone()).await.unwrap().is_none());
+
+        let session = driver.read(key.clone()).await.unwrap().unwrap();
+        assert_eq!(session.get_str("name"), Some("John"));
+        assert!(driver.cache().read(key).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_write_through() {
+        let driver = TieredDriver::new(MemoryDriver::new(), MemoryDriver::new());
+        let key = driver.create(data()).await.unwrap();
+
+        assert!(driver.cache().read(key.clone()).await.unwrap().is_some());
+        assert!(driver.store().read(key).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_evicts_old_key() {
+        let driver = TieredDriver::new(MemoryDriver::new(), MemoryDriver::new());
+        let key = driver.create(data()).await.unwrap();
+
+        let new_key = driver.regenerate(key.clone(), data()).await.unwrap();
+
+        assert_ne!(key, new_key);
+        assert!(driver.read(key).await.unwrap().is_none());
+        assert!(driver
+            .cache()
+            .read(new_key.clone())
+            .await
+            .unwrap()
+            .is_some());
+        assert!(driver.store().read(new_key).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_expired_cache_falls_back_to_store() {
+        let driver = TieredDriver::new(MemoryDriver::with_ttl(Duration::ZERO), MemoryDriver::new());
+        let key = driver.create(data()).await.unwrap();
+
+        assert!(driver.cache().read(key.clone()).await.unwrap().is_none());
+        assert!(driver.read(key).await.unwrap().is_some());
+    }
+}
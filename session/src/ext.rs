@@ -6,24 +6,46 @@ use crate::Session;
 pub trait RequestSessionExt {
     fn session(&self) -> Option<Session>;
     fn take_session(&mut self) -> Option<Session>;
+
+    /// Returns a copy of the session stored by the layer named `N`.
+    fn named_session<N: 'static>(&self) -> Option<Session<N>>;
+
+    /// Removes the session stored by the layer named `N`.
+    fn take_named_session<N: 'static>(&mut self) -> Option<Session<N>>;
 }
 
 impl RequestSessionExt for Request {
     fn session(&self) -> Option<Session> {
-        self.extensions().get::<Session>().cloned()
+        self.named_session()
     }
 
     fn take_session(&mut self) -> Option<Session> {
-        self.extensions_mut().remove::<Session>()
+        self.take_named_session()
+    }
+
+    fn named_session<N: 'static>(&self) -> Option<Session<N>> {
+        self.extensions().get::<Session<N>>().cloned()
+    }
+
+    fn take_named_session<N: 'static>(&mut self) -> Option<Session<N>> {
+        self.extensions_mut().remove::<Session<N>>()
     }
 }
 
 impl RequestSessionExt for Parts {
     fn session(&self) -> Option<Session> {
-        self.extensions.get::<Session>().cloned()
+        self.named_session()
     }
 
     fn take_session(&mut self) -> Option<Session> {
-        self.extensions.remove::<Session>()
+        self.take_named_session()
+    }
+
+    fn named_session<N: 'static>(&self) -> Option<Session<N>> {
+        self.extensions.get::<Session<N>>().cloned()
+    }
+
+    fn take_named_session<N: 'static>(&mut self) -> Option<Session<N>> {
+        self.extensions.remove::<Session<N>>()
     }
 }
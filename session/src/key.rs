@@ -1,4 +1,6 @@
-use std::{fmt, ops::Deref, sync::Arc};
+use std::{borrow::Cow, fmt, ops::Deref, sync::Arc};
+
+use crate::driver::generate_random_key;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct SessionKey(Arc<str>);
@@ -55,3 +57,190 @@ impl fmt::Debug for SessionKey {
         write!(f, "{}..{}", &self.0[..8], &self.0[self.0.len() - 8..])
     }
 }
+
+/// Generates new session keys and validates the keys presented by clients.
+///
+/// Incoming keys are validated before any driver I/O, so garbage or oversized cookie values never
+/// reach the session store.
+pub trait SessionKeyGenerator: fmt::Debug + Send + Sync {
+    /// Generates a new, unguessable session key.
+    fn generate(&self) -> SessionKey;
+
+    /// Returns `true` if `key` could have been produced by this generator.
+    fn validate(&self, key: &str) -> bool;
+}
+
+/// Generates random alphanumeric session keys with an optional prefix.
+///
+/// The default produces 40 alphanumeric characters, roughly 238 bits of entropy, well above the
+/// [OWASP recommendation](https://cheatsheetseries.owasp.org/cheatsheets/Session_Management_Cheat_Sheet.html#session-id-entropy).
+#[derive(Debug, Clone)]
+pub struct RandomKeyGenerator {
+    length: usize,
+    prefix: Option<Cow<'static, str>>,
+}
+
+impl RandomKeyGenerator {
+    /// Creates a new `RandomKeyGenerator` producing keys of 40 characters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of random characters in the key, excluding the prefix.
+    pub fn with_length(mut self, length: usize) -> Self {
+        self.length = length;
+        self
+    }
+
+    /// Sets a prefix prepended to every generated key.
+    pub fn with_prefix(mut self, prefix: impl Into<Cow<'static, str>>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+}
+
+impl Default for RandomKeyGenerator {
+    fn default() -> Self {
+        Self {
+            length: 40,
+            prefix: None,
+        }
+    }
+}
+
+impl SessionKeyGenerator for RandomKeyGenerator {
+    fn generate(&self) -> SessionKey {
+        let random = generate_random_key(self.length);
+        match &self.prefix {
+            Some(prefix) => SessionKey::new(format!("{}{}", prefix, random)),
+            None => SessionKey::new(random),
+        }
+    }
+
+    fn validate(&self, key: &str) -> bool {
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        key.strip_prefix(prefix).is_some_and(|random| {
+            random.len() == self.length && random.bytes().all(|byte| byte.is_ascii_alphanumeric())
+        })
+    }
+}
+
+/// Generates session keys signed with HMAC-SHA256.
+///
+/// Keys have the form `<random>.<signature>` where the signature is the base64url encoded MAC of
+/// the random part. Forged or tampered keys are rejected by [`validate`](Self::validate) without
+/// a round trip to the session store.
+#[cfg(feature = "signed")]
+#[cfg_attr(docsrs, doc(cfg(feature = "signed")))]
+#[derive(Clone)]
+pub struct SignedKeyGenerator {
+    inner: RandomKeyGenerator,
+    secret: Arc<[u8]>,
+}
+
+#[cfg(feature = "signed")]
+impl SignedKeyGenerator {
+    /// Creates a new `SignedKeyGenerator` using `secret` as the HMAC key.
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self::with_generator(secret, RandomKeyGenerator::default())
+    }
+
+    /// Creates a new `SignedKeyGenerator` signing the keys produced by `generator`.
+    pub fn with_generator(secret: impl AsRef<[u8]>, generator: RandomKeyGenerator) -> Self {
+        Self {
+            inner: generator,
+            secret: secret.as_ref().into(),
+        }
+    }
+
+    fn mac(&self, value: &str) -> hmac::Hmac<sha2::Sha256> {
+        use hmac::Mac;
+
+        let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(&self.secret)
+            .expect("HMAC can take a key of any size");
+        mac.update(value.as_bytes());
+        mac
+    }
+}
+
+#[cfg(feature = "signed")]
+impl SessionKeyGenerator for SignedKeyGenerator {
+    fn generate(&self) -> SessionKey {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+        use hmac::Mac;
+
+        let key = self.inner.generate();
+        let signature = self.mac(&key).finalize().into_bytes();
+        SessionKey::new(format!("{}.{}", key, URL_SAFE_NO_PAD.encode(signature)))
+    }
+
+    fn validate(&self, key: &str) -> bool {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+        use hmac::Mac;
+
+        let Some((value, signature)) = key.rsplit_once('.') else {
+            return false;
+        };
+        if !self.inner.validate(value) {
+            return false;
+        }
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+            return false;
+        };
+        self.mac(value).verify_slice(&signature).is_ok()
+    }
+}
+
+#[cfg(feature = "signed")]
+impl fmt::Debug for SignedKeyGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignedKeyGenerator")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_key_generator() {
+        let generator = RandomKeyGenerator::new();
+        let key = generator.generate();
+
+        assert_eq!(key.len(), 40);
+        assert!(generator.validate(&key));
+        assert!(!generator.validate(&key[1..]));
+        assert!(!generator.validate(&format!("{}!", &key[1..])));
+        assert!(!generator.validate(&"a".repeat(4096)));
+    }
+
+    #[test]
+    fn test_random_key_generator_prefix() {
+        let generator = RandomKeyGenerator::new()
+            .with_prefix("sess_")
+            .with_length(32);
+        let key = generator.generate();
+
+        assert!(key.starts_with("sess_"));
+        assert_eq!(key.len(), 37);
+        assert!(generator.validate(&key));
+        assert!(!generator.validate(&key[5..]));
+    }
+
+    #[cfg(feature = "signed")]
+    #[test]
+    fn test_signed_key_generator() {
+        let generator = SignedKeyGenerator::new("secret");
+        let key = generator.generate();
+
+        assert!(generator.validate(&key));
+        assert!(!SignedKeyGenerator::new("other").validate(&key));
+
+        let (value, signature) = key.rsplit_once('.').unwrap();
+        let forged = format!("{}.{}", RandomKeyGenerator::new().generate(), signature);
+        assert!(!generator.validate(&forged));
+        assert!(!generator.validate(value));
+    }
+}
@@ -8,7 +8,9 @@ use driver::generate_csrf_token;
 use error::SessionMissingFromExt;
 use ext::RequestSessionExt;
 use http::request::Parts;
-pub use key::SessionKey;
+#[cfg(feature = "signed")]
+pub use key::SignedKeyGenerator;
+pub use key::{RandomKeyGenerator, SessionKey, SessionKeyGenerator};
 
 pub mod middleware;
 mod state;
@@ -20,23 +22,41 @@ use axum_core::{
     response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
 };
 use state::Transition;
-use std::{borrow::Cow, collections::HashMap, convert::Infallible, ops::Deref};
+use std::{
+    borrow::Cow, collections::HashMap, convert::Infallible, fmt, marker::PhantomData, ops::Deref,
+};
 pub use subset::{SessionSubset, SessionSubsetKind};
 
 pub mod error;
 mod subset;
+#[cfg(any(test, feature = "testing"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub mod testing;
 
 pub(crate) type SessionData = HashMap<Cow<'static, str>, Value>;
 
+/// The name of the session when no name is given to the `SessionLayer`.
+#[derive(Debug, Clone, Copy)]
+pub struct DefaultSession;
+
 /// Represents a user session with data storage and management capabilities.
-#[derive(Debug, Clone)]
-pub struct Session {
+///
+/// The `N` marker type names the session. Each `SessionLayer` stores its session under its own
+/// name, so several layers can coexist on the same router with distinct cookies and drivers:
+///
+/// ```ignore
+/// struct Admin;
+///
+/// async fn handler(session: Session, admin: Session<Admin>) { /* ... */ }
+/// ```
+pub struct Session<N = DefaultSession> {
     key: SessionKey,
     state: SessionState,
     data: SessionData,
+    name: PhantomData<fn() -> N>,
 }
 
-impl Session {
+impl<N> Session<N> {
     /// Retrieves the session's key.
     pub fn key(&self) -> &str {
         &self.key
@@ -189,7 +209,7 @@ impl Session {
     }
 
     /// Retrieves a subset of session data containing only the specified keys.
-    pub fn only<'a, K>(&'a self, keys: &'a [K]) -> SessionSubset<'a, K>
+    pub fn only<'a, K>(&'a self, keys: &'a [K]) -> SessionSubset<'a, K, N>
     where
         K: AsRef<str>,
     {
@@ -199,11 +219,12 @@ impl Session {
             kind: SessionSubsetKind::Only,
             state: self.state,
             session_key: &self.key,
+            name: PhantomData,
         }
     }
 
     /// Retrieves all session data except the specified keys.
-    pub fn except<'a, K>(&'a self, keys: &'a [K]) -> SessionSubset<'a, K>
+    pub fn except<'a, K>(&'a self, keys: &'a [K]) -> SessionSubset<'a, K, N>
     where
         K: AsRef<str>,
     {
@@ -213,6 +234,7 @@ impl Session {
             kind: SessionSubsetKind::Except,
             session_key: &self.key,
             state: self.state,
+            name: PhantomData,
         }
     }
 
@@ -270,9 +292,44 @@ impl Session {
     pub(crate) fn into_parts(self) -> (SessionKey, SessionState, SessionData) {
         (self.key, self.state, self.data)
     }
+
+    /// Converts the session into a session with another name, keeping its key, state and data.
+    pub(crate) fn rename<M>(self) -> Session<M> {
+        Session {
+            key: self.key,
+            state: self.state,
+            data: self.data,
+            name: PhantomData,
+        }
+    }
+}
+
+impl<N> Clone for Session<N> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            state: self.state,
+            data: self.data.clone(),
+            name: PhantomData,
+        }
+    }
+}
+
+impl<N> fmt::Debug for Session<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("name", &std::any::type_name::<N>())
+            .field("key", &self.key)
+            .field("state", &self.state)
+            .field("data", &self.data)
+            .finish()
+    }
 }
 
-impl IntoResponseParts for Session {
+impl<N> IntoResponseParts for Session<N>
+where
+    N: 'static,
+{
     type Error = Infallible;
 
     fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
@@ -281,50 +338,55 @@ impl IntoResponseParts for Session {
     }
 }
 
-impl IntoResponse for Session {
+impl<N> IntoResponse for Session<N>
+where
+    N: 'static,
+{
     fn into_response(self) -> Response {
         (self, ()).into_response()
     }
 }
 
 #[async_trait::async_trait]
-impl<S> FromRequestParts<S> for Session
+impl<S, N> FromRequestParts<S> for Session<N>
 where
     S: Send + Sync + 'static,
+    N: 'static,
 {
     type Rejection = SessionMissingFromExt;
 
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
-        parts.take_session().ok_or(SessionMissingFromExt)
+        parts.take_named_session::<N>().ok_or(SessionMissingFromExt)
     }
 }
 
 #[derive(Debug)]
-pub struct CloneSession(Session);
+pub struct CloneSession<N = DefaultSession>(Session<N>);
 
-impl Deref for CloneSession {
-    type Target = Session;
+impl<N> Deref for CloneSession<N> {
+    type Target = Session<N>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
-impl CloneSession {
-    pub fn into_inner(self) -> Session {
+impl<N> CloneSession<N> {
+    pub fn into_inner(self) -> Session<N> {
         self.0
     }
 }
 
 #[async_trait::async_trait]
-impl<S> FromRequestParts<S> for CloneSession
+impl<S, N> FromRequestParts<S> for CloneSession<N>
 where
     S: Send + Sync + 'static,
+    N: 'static,
 {
     type Rejection = SessionMissingFromExt;
 
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
-        let session = parts.session().ok_or(SessionMissingFromExt)?;
+        let session = parts.named_session::<N>().ok_or(SessionMissingFromExt)?;
         Ok(Self(session))
     }
 }
@@ -341,10 +403,11 @@ mod tests {
         data.insert("is_student".into(), Value::Bool(true));
         data.insert("is_teacher".into(), Value::Bool(false));
 
-        let session = Session {
+        let session: Session = Session {
             key: "key".into(),
             state: SessionState::Unchanged,
             data,
+            name: PhantomData,
         };
 
         let keys = ["name", "age"];
@@ -367,10 +430,11 @@ mod tests {
         data.insert("is_student".into(), Value::Bool(true));
         data.insert("is_teacher".into(), Value::Bool(false));
 
-        let session = Session {
+        let session: Session = Session {
             key: "key".into(),
             state: SessionState::Unchanged,
             data,
+            name: PhantomData,
         };
 
         let keys = ["name", "age"];
@@ -393,10 +457,11 @@ mod tests {
         data.insert("is_student".into(), Value::Bool(true));
         data.insert("is_teacher".into(), Value::Bool(false));
 
-        let session = Session {
+        let session: Session = Session {
             key: "key".into(),
             state: SessionState::Unchanged,
             data,
+            name: PhantomData,
         };
 
         let all = session.all();
@@ -414,10 +479,11 @@ mod tests {
         data.insert("is_student".into(), Value::Bool(true));
         data.insert("is_teacher".into(), Value::Bool(false));
 
-        let session = Session {
+        let session: Session = Session {
             key: "key".into(),
             state: SessionState::Unchanged,
             data,
+            name: PhantomData,
         };
 
         let name = session.get::<String>("name").unwrap();
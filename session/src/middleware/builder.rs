@@ -1,11 +1,13 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, marker::PhantomData, sync::Arc};
 
 use crate::{
     driver::SessionDriver,
     error::{IntoErrorResponse, SessionError},
+    key::SessionKeyGenerator,
+    DefaultSession,
 };
 
-use super::{layer::SessionLayer, SessionKind};
+use super::{cookie::host_cookie, layer::SessionLayer, SessionConfig, SessionKind};
 
 #[derive(Debug)]
 pub struct DriverUnset;
@@ -14,7 +16,7 @@ pub struct DriverUnset;
 pub struct DriverSet;
 
 #[derive(Debug)]
-pub struct SessionLayerBuilder<D, H, DriverState = DriverUnset>
+pub struct SessionLayerBuilder<D, H, DriverState = DriverUnset, N = DefaultSession>
 where
     D: SessionDriver,
     H: IntoErrorResponse,
@@ -22,27 +24,31 @@ where
     pub(crate) driver: D,
     pub(crate) kind: SessionKind,
     pub(crate) error_handler: H,
-    pub(crate) _marker: std::marker::PhantomData<DriverState>,
+    pub(crate) config: SessionConfig,
+    pub(crate) name: PhantomData<fn() -> N>,
+    pub(crate) _marker: PhantomData<DriverState>,
 }
 
-impl<D, H, DriverState> SessionLayerBuilder<D, H, DriverState>
+impl<D, H, DriverState, N> SessionLayerBuilder<D, H, DriverState, N>
 where
     D: SessionDriver,
     H: IntoErrorResponse<Error = SessionError>,
 {
-    fn with_kind(self, kind: SessionKind) -> SessionLayerBuilder<D, H, DriverState> {
+    fn with_kind(self, kind: SessionKind) -> SessionLayerBuilder<D, H, DriverState, N> {
         SessionLayerBuilder {
             driver: self.driver,
             kind,
             error_handler: self.error_handler,
-            _marker: std::marker::PhantomData,
+            config: self.config,
+            name: PhantomData,
+            _marker: PhantomData,
         }
     }
 
     pub fn with_error_handler<HState>(
         self,
         handler: HState,
-    ) -> SessionLayerBuilder<D, HState, DriverState>
+    ) -> SessionLayerBuilder<D, HState, DriverState, N>
     where
         HState: IntoErrorResponse<Error = SessionError>,
     {
@@ -50,34 +56,106 @@ where
             driver: self.driver,
             kind: self.kind,
             error_handler: handler,
-            _marker: std::marker::PhantomData,
+            config: self.config,
+            name: PhantomData,
+            _marker: PhantomData,
         }
     }
 
-    pub fn with_cookie<C>(self, name: C) -> SessionLayerBuilder<D, H, DriverState>
+    pub fn with_cookie<C>(self, name: C) -> SessionLayerBuilder<D, H, DriverState, N>
     where
         C: Into<Cow<'static, str>>,
     {
         self.with_kind(SessionKind::Cookie(name.into()))
     }
+
+    /// Sets the generator used to create new session keys and to validate the keys sent by
+    /// clients before they reach the driver.
+    pub fn with_key_generator<G>(
+        mut self,
+        generator: G,
+    ) -> SessionLayerBuilder<D, H, DriverState, N>
+    where
+        G: SessionKeyGenerator + 'static,
+    {
+        self.config.generator = Arc::new(generator);
+        self
+    }
+
+    /// Names the session, so it can be extracted as `Session<M>` next to sessions from other
+    /// layers. The session cookie name should be changed too with
+    /// [`with_cookie`](Self::with_cookie).
+    pub fn named<M>(self) -> SessionLayerBuilder<D, H, DriverState, M> {
+        SessionLayerBuilder {
+            driver: self.driver,
+            kind: self.kind,
+            error_handler: self.error_handler,
+            config: self.config,
+            name: PhantomData,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Enables strict session keys to defend against session fixation.
+    ///
+    /// In strict mode a key sent by the client is only adopted when the driver returns a session
+    /// stored under exactly that key. A session returned by the handler under any other key is
+    /// moved to a freshly generated key instead of being written where the handler asked, so an
+    /// attacker chosen ID is never persisted.
+    pub fn with_strict_mode(mut self) -> SessionLayerBuilder<D, H, DriverState, N> {
+        self.config.strict = true;
+        self
+    }
+
+    /// Prefixes the session cookie name with `__Host-`.
+    ///
+    /// Browsers only accept such a cookie over HTTPS, from the exact host that set it, so it
+    /// cannot be planted by a sibling subdomain. The cookie is marked `Secure` with `Path=/` and
+    /// no `Domain`, as the prefix requires.
+    pub fn with_host_cookie(mut self) -> SessionLayerBuilder<D, H, DriverState, N> {
+        self.config.host_cookie = true;
+        self
+    }
+
+    /// Reads and writes the session cookie through a `cortev-cookie` jar.
+    ///
+    /// The session cookie is signed or encrypted with the key of the jar when its
+    /// `EncryptionCookiePolicy` says so, and the jar defaults for path, domain, `Secure` and
+    /// `SameSite` are applied to it. Cookies that fail verification are treated as missing.
+    /// The session cookie is set whatever the consent of the user, and a session cookie the jar
+    /// refuses, for instance because it exceeds the size limit, fails the request.
+    #[cfg(feature = "cortev-cookie")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "cortev-cookie")))]
+    pub fn with_cookie_jar(
+        mut self,
+        jar: cortev_cookie::CookieJar,
+    ) -> SessionLayerBuilder<D, H, DriverState, N> {
+        self.config.cookie_jar = Some(jar);
+        self
+    }
 }
 
-impl<D, H> SessionLayerBuilder<D, H, DriverSet>
+impl<D, H, N> SessionLayerBuilder<D, H, DriverSet, N>
 where
     D: SessionDriver,
     H: IntoErrorResponse<Error = SessionError>,
 {
-    pub fn build(self) -> SessionLayer<D, H> {
-        SessionLayer::new(self.driver, self.kind, self.error_handler)
+    pub fn build(self) -> SessionLayer<D, H, N> {
+        let kind = if self.config.host_cookie {
+            host_cookie(self.kind)
+        } else {
+            self.kind
+        };
+        SessionLayer::new(self.driver, kind, self.error_handler).with_config(self.config)
     }
 }
 
-impl<D, H> SessionLayerBuilder<D, H, DriverUnset>
+impl<D, H, N> SessionLayerBuilder<D, H, DriverUnset, N>
 where
     D: SessionDriver,
     H: IntoErrorResponse<Error = SessionError>,
 {
-    pub fn with_driver<DState>(self, driver: DState) -> SessionLayerBuilder<DState, H, DriverSet>
+    pub fn with_driver<DState>(self, driver: DState) -> SessionLayerBuilder<DState, H, DriverSet, N>
     where
         DState: SessionDriver,
     {
@@ -85,7 +163,9 @@ where
             driver,
             kind: self.kind,
             error_handler: self.error_handler,
-            _marker: std::marker::PhantomData::<DriverSet>,
+            config: self.config,
+            name: PhantomData,
+            _marker: PhantomData::<DriverSet>,
         }
     }
 }
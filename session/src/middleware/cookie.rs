@@ -3,6 +3,58 @@ use std::borrow::Cow;
 use cookie::Cookie;
 use http::{header, HeaderMap};
 
+use super::{SessionConfig, SessionKind};
+use crate::error::SessionError;
+
+/// A cookie name prefix browsers enforce requirements for, as the `CookiePrefix` of
+/// `cortev-cookie`, which is an optional dependency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prefix {
+    Host,
+    Secure,
+}
+
+impl Prefix {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Host => "__Host-",
+            Self::Secure => "__Secure-",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Host, Self::Secure]
+            .into_iter()
+            .find(|prefix| name.starts_with(prefix.as_str()))
+    }
+
+    /// Adjusts `cookie` to meet the requirements of this prefix.
+    fn normalize(self, cookie: &mut Cookie<'_>) {
+        cookie.set_secure(true);
+        if self == Self::Host {
+            cookie.unset_domain();
+            cookie.set_path("/");
+        }
+    }
+}
+
+/// Prefixes the session cookie name with `__Host-`, unless it already is.
+pub(crate) fn host_cookie(kind: SessionKind) -> SessionKind {
+    match kind {
+        SessionKind::Cookie(name) if Prefix::from_name(&name) != Some(Prefix::Host) => {
+            SessionKind::Cookie(format!("{}{name}", Prefix::Host.as_str()).into())
+        }
+        kind @ SessionKind::Cookie(_) => kind,
+    }
+}
+
+/// Adjusts a `__Host-` or `__Secure-` cookie so browsers accept it.
+pub(crate) fn apply_prefix(cookie: &mut Cookie<'_>) {
+    if let Some(prefix) = Prefix::from_name(cookie.name()) {
+        prefix.normalize(cookie);
+    }
+}
+
 #[cfg_attr(feature = "tracing", tracing::instrument(skip(headers, cookie_name)))]
 pub(crate) fn session_cookie(
     headers: &HeaderMap,
@@ -25,6 +77,48 @@ pub(crate) fn session_cookie(
     value
 }
 
+/// Returns the session cookie, verified or decrypted by the cookie jar when one is configured.
+#[cfg_attr(not(feature = "cortev-cookie"), allow(unused_variables))]
+pub(crate) fn read_session_cookie(
+    config: &SessionConfig,
+    headers: &HeaderMap,
+    cookie_name: Cow<'static, str>,
+) -> Option<Cookie<'static>> {
+    #[cfg(feature = "cortev-cookie")]
+    if let Some(jar) = &config.cookie_jar {
+        return jar.clone().from_headers(headers).get(cookie_name);
+    }
+
+    session_cookie(headers, cookie_name).map(Cookie::into_owned)
+}
+
+/// Sets the session cookie, signed or encrypted by the cookie jar when one is configured.
+///
+/// The session cookie is essential, so the consent policy of the jar does not apply to it.
+/// Fails when the jar refuses the cookie, such as when it exceeds the size limit or violates
+/// its prefix under `PrefixEnforcement::Reject`.
+#[cfg_attr(not(feature = "cortev-cookie"), allow(unused_variables))]
+pub(crate) fn write_session_cookie(
+    config: &SessionConfig,
+    cookie: Cookie<'static>,
+    headers: &mut HeaderMap,
+) -> Result<(), SessionError> {
+    #[cfg(feature = "cortev-cookie")]
+    if let Some(jar) = &config.cookie_jar {
+        let jar = jar
+            .clone()
+            .try_insert_essential(cookie)
+            .map_err(|error| SessionError::Other(Box::new(error)))?;
+        for cookie in jar.queued() {
+            set_cookie(cookie.clone(), headers);
+        }
+        return Ok(());
+    }
+
+    set_cookie(cookie, headers);
+    Ok(())
+}
+
 pub(crate) fn set_cookie(cookie: Cookie<'static>, headers: &mut HeaderMap) {
     if let Ok(header_value) = cookie.encoded().to_string().parse() {
         headers.append(header::SET_COOKIE, header_value);
@@ -1,16 +1,25 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, marker::PhantomData, sync::Arc};
 
 use tower_layer::Layer;
 
 use crate::{
     driver::{NullDriver, SessionDriver},
     error::{DefaultErrorHandler, IntoErrorResponse},
+    key::SessionKeyGenerator,
+    DefaultSession,
 };
 
-use super::{builder::SessionLayerBuilder, SessionKind, SessionMiddleware};
+use super::{
+    builder::SessionLayerBuilder, cookie::host_cookie, SessionConfig, SessionKind,
+    SessionMiddleware,
+};
 
-#[derive(Debug, Clone)]
-pub struct SessionLayer<D, H>
+/// A layer that loads the session before the request and persists it after the response.
+///
+/// The `N` marker type names the session, see [`Session`](crate::Session). Layers with distinct
+/// names can be stacked on the same router, each with its own cookie and driver.
+#[derive(Debug)]
+pub struct SessionLayer<D, H, N = DefaultSession>
 where
     D: SessionDriver,
     H: IntoErrorResponse,
@@ -18,6 +27,24 @@ where
     driver: D,
     kind: SessionKind,
     error_handler: H,
+    config: SessionConfig,
+    name: PhantomData<fn() -> N>,
+}
+
+impl<D, H, N> Clone for SessionLayer<D, H, N>
+where
+    D: SessionDriver + Clone,
+    H: IntoErrorResponse + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            driver: self.driver.clone(),
+            kind: self.kind.clone(),
+            error_handler: self.error_handler.clone(),
+            config: self.config.clone(),
+            name: PhantomData,
+        }
+    }
 }
 
 impl SessionLayer<NullDriver, DefaultErrorHandler> {
@@ -26,12 +53,14 @@ impl SessionLayer<NullDriver, DefaultErrorHandler> {
             driver: NullDriver::new(),
             kind: SessionKind::Cookie(Cow::Borrowed("id")),
             error_handler: DefaultErrorHandler,
-            _marker: std::marker::PhantomData,
+            config: SessionConfig::default(),
+            name: PhantomData,
+            _marker: PhantomData,
         }
     }
 }
 
-impl<D, H> SessionLayer<D, H>
+impl<D, H, N> SessionLayer<D, H, N>
 where
     D: SessionDriver,
     H: IntoErrorResponse,
@@ -41,16 +70,62 @@ where
             driver,
             kind,
             error_handler,
+            config: SessionConfig::default(),
+            name: PhantomData,
         }
     }
+
+    /// Sets the generator used to create and validate session keys.
+    pub fn with_key_generator<G>(mut self, generator: G) -> Self
+    where
+        G: SessionKeyGenerator + 'static,
+    {
+        self.config.generator = Arc::new(generator);
+        self
+    }
+
+    /// Enables or disables strict session keys, see
+    /// [`SessionLayerBuilder::with_strict_mode`](super::builder::SessionLayerBuilder::with_strict_mode).
+    pub fn with_strict_mode(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self
+    }
+
+    /// Prefixes the session cookie name with `__Host-`, see
+    /// [`SessionLayerBuilder::with_host_cookie`](super::builder::SessionLayerBuilder::with_host_cookie).
+    pub fn with_host_cookie(mut self) -> Self {
+        self.config.host_cookie = true;
+        self.kind = host_cookie(self.kind);
+        self
+    }
+
+    pub(crate) fn with_config(mut self, config: SessionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn driver(&self) -> &D {
+        &self.driver
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn kind(&self) -> &SessionKind {
+        &self.kind
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn config(&self) -> &SessionConfig {
+        &self.config
+    }
 }
 
-impl<S, D, H> Layer<S> for SessionLayer<D, H>
+impl<S, D, H, N> Layer<S> for SessionLayer<D, H, N>
 where
     D: SessionDriver + Clone,
     H: IntoErrorResponse + Clone,
 {
-    type Service = SessionMiddleware<S, D, H>;
+    type Service = SessionMiddleware<S, D, H, N>;
 
     fn layer(&self, inner: S) -> Self::Service {
         SessionMiddleware::new(
@@ -59,5 +134,6 @@ where
             self.kind.clone(),
             self.error_handler.clone(),
         )
+        .with_config(self.config.clone())
     }
 }
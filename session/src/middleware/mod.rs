@@ -1,9 +1,11 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, marker::PhantomData, sync::Arc};
 
-use crate::error::IntoErrorResponse;
+use crate::{
+    error::IntoErrorResponse, key::SessionKeyGenerator, DefaultSession, RandomKeyGenerator,
+};
 
-mod builder;
-mod cookie;
+pub mod builder;
+pub(crate) mod cookie;
 pub mod future;
 mod layer;
 mod service;
@@ -16,8 +18,34 @@ pub enum SessionKind {
     Cookie(Cow<'static, str>),
 }
 
+/// Options shared by the session layer and middleware.
 #[derive(Debug, Clone)]
-pub struct SessionMiddleware<S, D, H>
+pub(crate) struct SessionConfig {
+    /// Generates new session keys and validates the ones sent by clients.
+    pub(crate) generator: Arc<dyn SessionKeyGenerator>,
+    /// Only adopt a client supplied key when the driver returns a session for exactly that key.
+    pub(crate) strict: bool,
+    /// Prefix the session cookie name with `__Host-`.
+    pub(crate) host_cookie: bool,
+    /// Signs or encrypts the session cookie according to its encryption policy.
+    #[cfg(feature = "cortev-cookie")]
+    pub(crate) cookie_jar: Option<cortev_cookie::CookieJar>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            generator: Arc::new(RandomKeyGenerator::default()),
+            strict: false,
+            host_cookie: false,
+            #[cfg(feature = "cortev-cookie")]
+            cookie_jar: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SessionMiddleware<S, D, H, N = DefaultSession>
 where
     D: SessionDriver,
     H: IntoErrorResponse,
@@ -26,9 +54,29 @@ where
     driver: D,
     kind: SessionKind,
     error_handler: H,
+    config: SessionConfig,
+    name: PhantomData<fn() -> N>,
+}
+
+impl<S, D, H, N> Clone for SessionMiddleware<S, D, H, N>
+where
+    S: Clone,
+    D: SessionDriver + Clone,
+    H: IntoErrorResponse + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            driver: self.driver.clone(),
+            kind: self.kind.clone(),
+            error_handler: self.error_handler.clone(),
+            config: self.config.clone(),
+            name: PhantomData,
+        }
+    }
 }
 
-impl<S, D, H> SessionMiddleware<S, D, H>
+impl<S, D, H, N> SessionMiddleware<S, D, H, N>
 where
     D: SessionDriver,
     H: IntoErrorResponse,
@@ -39,6 +87,37 @@ where
             driver,
             kind,
             error_handler: handler,
+            config: SessionConfig::default(),
+            name: PhantomData,
         }
     }
+
+    /// Sets the generator used to create and validate session keys.
+    pub fn with_key_generator<G>(mut self, generator: G) -> Self
+    where
+        G: SessionKeyGenerator + 'static,
+    {
+        self.config.generator = Arc::new(generator);
+        self
+    }
+
+    /// Enables or disables strict session keys, see
+    /// [`SessionLayerBuilder::with_strict_mode`](builder::SessionLayerBuilder::with_strict_mode).
+    pub fn with_strict_mode(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self
+    }
+
+    /// Prefixes the session cookie name with `__Host-`, see
+    /// [`SessionLayerBuilder::with_host_cookie`](builder::SessionLayerBuilder::with_host_cookie).
+    pub fn with_host_cookie(mut self) -> Self {
+        self.config.host_cookie = true;
+        self.kind = cookie::host_cookie(self.kind);
+        self
+    }
+
+    pub(crate) fn with_config(mut self, config: SessionConfig) -> Self {
+        self.config = config;
+        self
+    }
 }
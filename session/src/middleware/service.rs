@@ -5,7 +5,7 @@ use crate::{
     driver::{SessionDriver, TokenExt},
     error::{IntoErrorResponse, SessionError},
     middleware::{
-        cookie::{session_cookie, set_cookie},
+        cookie::{apply_prefix, read_session_cookie, write_session_cookie},
         SessionKind,
     },
     Session, SessionData, SessionState,
@@ -20,8 +20,9 @@ use tower_service::Service;
 
 use super::{future::ResponseFuture, SessionMiddleware};
 
-impl<S, D, H> Service<extract::Request> for SessionMiddleware<S, D, H>
+impl<S, D, H, N> Service<extract::Request> for SessionMiddleware<S, D, H, N>
 where
+    N: 'static,
     S: Service<extract::Request, Response = axum_core::response::Response, Error = Infallible>
         + Clone
         + Send
@@ -57,14 +58,34 @@ where
         let driver = self.driver.clone();
         let kind = self.kind.clone();
         let handler = self.error_handler.clone();
+        let config = self.config.clone();
         let future = Box::pin(async move {
             let session_key = match kind {
-                SessionKind::Cookie(ref id) => session_cookie(req.headers(), id.clone()),
+                SessionKind::Cookie(ref id) => {
+                    read_session_cookie(&config, req.headers(), id.clone())
+                }
             };
 
+            let session_key = session_key.filter(|cookie| {
+                let valid = config.generator.validate(cookie.value());
+                #[cfg(feature = "tracing")]
+                if !valid {
+                    tracing::warn!("Rejecting malformed session key");
+                }
+                valid
+            });
+
             let maybe_session = if let Some(cookie) = session_key {
                 let key = cookie.value();
                 match driver.read(key.into()).await {
+                    Ok(session) if config.strict => session.filter(|session| {
+                        let adopted = session.key() == key;
+                        #[cfg(feature = "tracing")]
+                        if !adopted {
+                            tracing::warn!("Driver returned a session under a different key");
+                        }
+                        adopted
+                    }),
                     Ok(session) => session,
                     Err(err) => {
                         #[cfg(feature = "tracing")]
@@ -81,7 +102,10 @@ where
                 session
             } else {
                 let data = SessionData::session();
-                let key = match driver.create(data.clone()).await {
+                let key = match driver
+                    .write(config.generator.generate(), data.clone())
+                    .await
+                {
                     Ok(value) => value,
                     Err(err) => {
                         #[cfg(feature = "tracing")]
@@ -95,14 +119,14 @@ where
 
             let session_key = session.key.clone();
 
-            req.extensions_mut().insert(session);
+            req.extensions_mut().insert(session.rename::<N>());
 
             let mut response = match ready_inner.call(req).await {
                 Ok(response) => response,
                 Err(_err) => unreachable!(), // Infallible
             };
 
-            let extension = response.extensions_mut().remove::<Session>();
+            let extension = response.extensions_mut().remove::<Session<N>>();
 
             let session_key = if let Some(session) = extension {
                 let (key, state, data) = session.into_parts();
@@ -111,9 +135,24 @@ where
                 tracing::debug!("Session state {}", state);
 
                 let session_key = match state {
+                    _ if config.strict && key != session_key => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!("Handler returned a session under a foreign key");
+                        driver
+                            .regenerate_to(session_key, config.generator.generate(), data)
+                            .await
+                    }
                     SessionState::Changed => driver.write(key, data).await,
-                    SessionState::Regenerated => driver.regenerate(key, data).await,
-                    SessionState::Invalidated => driver.invalidate(key, data).await,
+                    SessionState::Regenerated => {
+                        driver
+                            .regenerate_to(key, config.generator.generate(), data)
+                            .await
+                    }
+                    SessionState::Invalidated => {
+                        driver
+                            .invalidate_to(key, config.generator.generate(), data)
+                            .await
+                    }
                     SessionState::Unchanged => Ok(key),
                 };
                 match session_key {
@@ -138,11 +177,17 @@ where
                     let time = driver.ttl().as_secs();
                     let max_age = CookieDuration::seconds(time as i64);
                     cookie.set_max_age(max_age);
+                    apply_prefix(&mut cookie);
                     cookie
                 }
             };
 
-            set_cookie(cookie, response.headers_mut());
+            if let Err(err) = write_session_cookie(&config, cookie, response.headers_mut()) {
+                #[cfg(feature = "tracing")]
+                tracing::error!(error = %crate::error::log_error_chain(&err));
+
+                return handler.into_error_response(err);
+            }
 
             #[cfg(feature = "tracing")]
             tracing::debug!("Session middleware finished");
@@ -153,3 +198,344 @@ where
         ResponseFuture { inner: future }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum_core::body::Body;
+    use http::header;
+    use tower::{service_fn, ServiceExt};
+    use tower_layer::Layer;
+
+    use super::*;
+    use crate::{
+        driver::NullDriver, error::DefaultErrorHandler, ext::RequestSessionExt,
+        middleware::SessionLayer, SessionKeyGenerator,
+    };
+
+    const ATTACKER_KEY: &str = "AttackerChosenSessionIdentifier000000000";
+
+    fn issued_key(response: &Response) -> String {
+        let header = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        Cookie::parse_encoded(header).unwrap().value().to_owned()
+    }
+
+    async fn call<D>(
+        driver: D,
+        strict: bool,
+        cookie: &str,
+        respond: fn(Session) -> Response,
+    ) -> Response
+    where
+        D: SessionDriver + Clone + Send + 'static,
+    {
+        let builder = SessionLayer::builder().with_driver(driver);
+        let builder = if strict {
+            builder.with_strict_mode()
+        } else {
+            builder
+        };
+        let service = builder
+            .build()
+            .layer(service_fn(move |mut req: extract::Request| {
+                let session = req.take_session().unwrap();
+                async move { Ok::<_, Infallible>(respond(session)) }
+            }));
+
+        let request = extract::Request::builder()
+            .header(header::COOKIE, format!("id={}", cookie))
+            .body(Body::empty())
+            .unwrap();
+        service.oneshot(request).await.unwrap()
+    }
+
+    fn login(session: Session) -> Response {
+        session.insert("user_id", 1).into_response()
+    }
+
+    #[cfg(feature = "memory")]
+    fn forge(_session: Session) -> Response {
+        Session::builder(ATTACKER_KEY)
+            .with_data(SessionData::default())
+            .build()
+            .insert("user_id", 1)
+            .into_response()
+    }
+
+    #[tokio::test]
+    async fn test_null_driver_never_adopts_client_key() {
+        for strict in [false, true] {
+            let response = call(NullDriver::new(), strict, ATTACKER_KEY, login).await;
+            let key = issued_key(&response);
+
+            assert_ne!(key, ATTACKER_KEY);
+            assert_eq!(key.len(), 40);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_key_is_replaced() {
+        for cookie in ["short", "../../etc/passwd", &"a".repeat(4096)] {
+            let response = call(NullDriver::new(), false, cookie, login).await;
+            assert_ne!(issued_key(&response), cookie);
+        }
+    }
+
+    #[cfg(feature = "memory")]
+    #[tokio::test]
+    async fn test_memory_driver_never_adopts_unknown_key() {
+        use crate::driver::MemoryDriver;
+
+        for strict in [false, true] {
+            let driver = MemoryDriver::new();
+            let response = call(driver.clone(), strict, ATTACKER_KEY, login).await;
+            let key = issued_key(&response);
+
+            assert_ne!(key, ATTACKER_KEY);
+            assert!(driver.read(ATTACKER_KEY.into()).await.unwrap().is_none());
+            assert!(driver.read(key.into()).await.unwrap().is_some());
+        }
+    }
+
+    #[cfg(feature = "memory")]
+    #[tokio::test]
+    async fn test_strict_mode_ignores_foreign_handler_key() {
+        use crate::{
+            driver::MemoryDriver,
+            testing::{DriverCall, RecordingDriver},
+        };
+
+        let driver = RecordingDriver::new(MemoryDriver::new());
+        let response = call(driver.clone(), true, ATTACKER_KEY, forge).await;
+        let key = issued_key(&response);
+
+        assert_ne!(key, ATTACKER_KEY);
+        assert!(driver.read(ATTACKER_KEY.into()).await.unwrap().is_none());
+
+        let Some(DriverCall::Write(created, _)) = driver.calls().into_iter().nth(1) else {
+            panic!(
+                "expected a session to be created, calls were {:?}",
+                driver.calls()
+            );
+        };
+        assert_ne!(created.to_string(), key);
+        assert!(driver.read(created).await.unwrap().is_none());
+
+        let session = driver.read(key.into()).await.unwrap().unwrap();
+        assert_eq!(session.get::<i32>("user_id"), Some(1));
+    }
+
+    struct Admin;
+
+    #[tokio::test]
+    async fn test_named_sessions_coexist() {
+        let public = SessionLayer::builder()
+            .with_driver(NullDriver::new())
+            .build();
+        let admin = SessionLayer::builder()
+            .with_driver(NullDriver::new())
+            .with_cookie("admin_id")
+            .named::<Admin>()
+            .build();
+
+        let service = admin.layer(public.layer(service_fn(|mut req: extract::Request| {
+            let public = req.take_session();
+            let admin = req.take_named_session::<Admin>();
+            async move {
+                assert!(public.is_some());
+                assert!(admin.is_some());
+                Ok::<_, Infallible>(Response::default())
+            }
+        })));
+
+        let request = extract::Request::builder().body(Body::empty()).unwrap();
+        let response = service.oneshot(request).await.unwrap();
+
+        let names: Vec<_> = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .into_iter()
+            .map(|value| {
+                let cookie = Cookie::parse_encoded(value.to_str().unwrap()).unwrap();
+                cookie.name().to_owned()
+            })
+            .collect();
+        assert_eq!(names, ["id", "admin_id"]);
+    }
+
+    #[derive(Debug, Clone)]
+    struct MismatchDriver;
+
+    impl SessionDriver for MismatchDriver {
+        async fn read(&self, _key: crate::SessionKey) -> Result<Option<Session>, SessionError> {
+            let session = Session::builder(ATTACKER_KEY)
+                .with_data(SessionData::default())
+                .build();
+            Ok(Some(session))
+        }
+
+        async fn write(
+            &self,
+            key: crate::SessionKey,
+            _data: SessionData,
+        ) -> Result<crate::SessionKey, SessionError> {
+            Ok(key)
+        }
+
+        async fn destroy(&self, _key: crate::SessionKey) -> Result<(), SessionError> {
+            Ok(())
+        }
+
+        fn ttl(&self) -> std::time::Duration {
+            std::time::Duration::from_secs(60)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_rejects_mismatched_read() {
+        let cookie = crate::RandomKeyGenerator::new().generate();
+        let response = call(MismatchDriver, true, &cookie, login).await;
+        let key = issued_key(&response);
+
+        assert_ne!(key, ATTACKER_KEY);
+        assert_ne!(key, cookie.to_string());
+    }
+
+    #[cfg(all(feature = "cortev-cookie", feature = "memory"))]
+    #[tokio::test]
+    async fn test_encrypted_session_cookie() {
+        use cortev_cookie::{CookieJar, CookieKind, EncryptionCookiePolicy};
+
+        use crate::driver::MemoryDriver;
+
+        let key = cookie::Key::generate();
+        let mut policy = EncryptionCookiePolicy::default();
+        policy.insert("id", CookieKind::Private);
+        let jar = CookieJar::builder(key.clone())
+            .with_encryption_policy(policy)
+            .build();
+
+        let driver = MemoryDriver::new();
+        let layer = SessionLayer::builder()
+            .with_driver(driver.clone())
+            .with_cookie_jar(jar)
+            .build();
+        let service = layer.layer(service_fn(|mut req: extract::Request| {
+            let session = req.take_session().unwrap();
+            async move { Ok::<_, Infallible>(login(session)) }
+        }));
+
+        let request = extract::Request::builder().body(Body::empty()).unwrap();
+        let response = service.clone().oneshot(request).await.unwrap();
+        let encrypted = issued_key(&response);
+
+        let mut decrypted = cookie::CookieJar::new();
+        decrypted.add_original(Cookie::new("id", encrypted.clone()));
+        let key_value = decrypted
+            .private(&key)
+            .get("id")
+            .unwrap()
+            .value()
+            .to_owned();
+        assert_ne!(encrypted, key_value);
+        assert!(driver
+            .read(key_value.clone().into())
+            .await
+            .unwrap()
+            .is_some());
+
+        let request = extract::Request::builder()
+            .header(
+                header::COOKIE,
+                Cookie::new("id", encrypted).encoded().to_string(),
+            )
+            .body(Body::empty())
+            .unwrap();
+        let response = service.clone().oneshot(request).await.unwrap();
+        let mut decrypted = cookie::CookieJar::new();
+        decrypted.add_original(Cookie::new("id", issued_key(&response)));
+        assert_eq!(
+            decrypted.private(&key).get("id").unwrap().value(),
+            key_value
+        );
+
+        let request = extract::Request::builder()
+            .header(header::COOKIE, format!("id={}", key_value))
+            .body(Body::empty())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        let mut decrypted = cookie::CookieJar::new();
+        decrypted.add_original(Cookie::new("id", issued_key(&response)));
+        assert_ne!(
+            decrypted.private(&key).get("id").unwrap().value(),
+            key_value
+        );
+    }
+
+    #[cfg(feature = "cortev-cookie")]
+    #[tokio::test]
+    async fn test_session_cookie_through_restrictive_jar() {
+        use cortev_cookie::{ConsentPolicy, CookieCategory, CookieJar};
+        use http::StatusCode;
+
+        let call = |jar: CookieJar| {
+            let layer = SessionLayer::builder()
+                .with_driver(NullDriver::new())
+                .with_cookie_jar(jar)
+                .build();
+            let service = layer.layer(service_fn(|mut req: extract::Request| {
+                let session = req.take_session().unwrap();
+                async move { Ok::<_, Infallible>(login(session)) }
+            }));
+            let request = extract::Request::builder().body(Body::empty()).unwrap();
+            service.oneshot(request)
+        };
+
+        let policy = ConsentPolicy::new("consent").with_default_category(CookieCategory::Marketing);
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .with_consent_policy(policy)
+            .build();
+        let response = call(jar).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!issued_key(&response).is_empty());
+
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .with_max_cookie_size(8)
+            .build();
+        let response = call(jar).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!response.headers().contains_key(header::SET_COOKIE));
+    }
+
+    #[tokio::test]
+    async fn test_host_session_cookie() {
+        let built = SessionLayer::builder()
+            .with_driver(NullDriver::new())
+            .with_cookie("sid")
+            .with_host_cookie()
+            .build();
+        let layered = SessionLayer::new(
+            NullDriver::new(),
+            SessionKind::Cookie("sid".into()),
+            DefaultErrorHandler,
+        )
+        .with_host_cookie()
+        .with_host_cookie();
+
+        for layer in [built, layered] {
+            let service = layer.layer(service_fn(|mut req: extract::Request| {
+                let session = req.take_session().unwrap();
+                async move { Ok::<_, Infallible>(login(session)) }
+            }));
+
+            let request = extract::Request::builder().body(Body::empty()).unwrap();
+            let response = service.oneshot(request).await.unwrap();
+
+            let header = response.headers()[header::SET_COOKIE].to_str().unwrap();
+            let cookie = Cookie::parse_encoded(header).unwrap();
+            assert_eq!(cookie.name(), "__Host-sid");
+            assert_eq!(cookie.secure(), Some(true));
+            assert_eq!(cookie.path(), Some("/"));
+            assert_eq!(cookie.domain(), None);
+        }
+    }
+}
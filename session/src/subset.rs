@@ -1,6 +1,8 @@
+use std::marker::PhantomData;
+
 use serde_json::Value;
 
-use crate::{state::Transition, Session, SessionData, SessionKey, SessionState};
+use crate::{state::Transition, DefaultSession, Session, SessionData, SessionKey, SessionState};
 
 #[derive(Debug, Clone, Copy)]
 pub enum SessionSubsetKind {
@@ -13,7 +15,7 @@ pub enum SessionSubsetKind {
 /// Allows users to work with a subset of a session's data, either including
 /// or excluding specified keys based on the subset kind.
 #[derive(Debug)]
-pub struct SessionSubset<'a, K> {
+pub struct SessionSubset<'a, K, N = DefaultSession> {
     /// Reference to the full session data.
     pub(crate) data: &'a SessionData,
     /// The keys used to filter the session data.
@@ -24,9 +26,11 @@ pub struct SessionSubset<'a, K> {
     pub(crate) session_key: &'a SessionKey,
     /// The current state of the session associated with this subset.
     pub(crate) state: SessionState,
+    /// The name of the session this subset was taken from.
+    pub(crate) name: PhantomData<fn() -> N>,
 }
 
-impl<K> SessionSubset<'_, K>
+impl<K, N> SessionSubset<'_, K, N>
 where
     K: AsRef<str>,
 {
@@ -80,11 +84,12 @@ where
     ///
     /// The resulting session inherits the state of the parent session, with
     /// the state transitioned to `Changed`.
-    pub fn into_session(self) -> Session {
+    pub fn into_session(self) -> Session<N> {
         Session {
             key: self.session_key.clone(),
             state: self.state.transition(SessionState::Changed),
             data: self.to_all(),
+            name: PhantomData,
         }
     }
 }
@@ -101,10 +106,11 @@ mod tests {
         data.insert("is_student".into(), Value::Bool(true));
         data.insert("is_teacher".into(), Value::Bool(false));
 
-        let session = Session {
+        let session: Session = Session {
             key: "key".into(),
             state: SessionState::Unchanged,
             data,
+            name: PhantomData,
         };
 
         let keys = ["name", "age"];
@@ -0,0 +1,399 @@
+//! Utilities for testing handlers and middleware that use sessions.
+//!
+//! Enable the `testing` feature to use this module from your own tests:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! cortev-session = { version = "0.1", features = ["testing"] }
+//! ```
+use std::{
+    borrow::Cow,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum_core::{body::Body, extract::Request, response::Response};
+use cookie::Cookie;
+use http::{header, HeaderMap};
+use serde_json::Value;
+use tower::ServiceExt;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    builder::BuildSession,
+    driver::{SessionDriver, SessionResult},
+    error::IntoErrorResponse,
+    middleware::{
+        cookie::{read_session_cookie, write_session_cookie},
+        SessionKind, SessionLayer,
+    },
+    RandomKeyGenerator, Session, SessionData, SessionKey, SessionKeyGenerator, SessionState,
+};
+
+/// A call made to a [`RecordingDriver`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum DriverCall {
+    Read(SessionKey),
+    Write(SessionKey, SessionData),
+    Destroy(SessionKey),
+    Regenerate { from: SessionKey, to: SessionKey },
+    Invalidate { from: SessionKey, to: SessionKey },
+}
+
+/// A driver that records every call before forwarding it to the inner driver.
+///
+/// Clones share the same record, so a clone can be handed to the `SessionLayer` while the test
+/// keeps the original to inspect the calls.
+#[derive(Debug, Clone)]
+pub struct RecordingDriver<D> {
+    inner: D,
+    calls: Arc<Mutex<Vec<DriverCall>>>,
+}
+
+impl<D> RecordingDriver<D>
+where
+    D: SessionDriver,
+{
+    /// Creates a new `RecordingDriver` forwarding to `inner`.
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            calls: Arc::default(),
+        }
+    }
+
+    /// Returns the inner driver.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Returns every call recorded so far, in order.
+    pub fn calls(&self) -> Vec<DriverCall> {
+        self.calls
+            .lock()
+            .expect("the call record is poisoned")
+            .clone()
+    }
+
+    /// Forgets every call recorded so far.
+    pub fn clear(&self) {
+        self.calls
+            .lock()
+            .expect("the call record is poisoned")
+            .clear();
+    }
+
+    /// Asserts that a session was regenerated.
+    #[track_caller]
+    pub fn assert_regenerated(&self) {
+        let calls = self.calls();
+        assert!(
+            calls
+                .iter()
+                .any(|call| matches!(call, DriverCall::Regenerate { .. })),
+            "expected the session to be regenerated, calls were {:?}",
+            calls
+        );
+    }
+
+    /// Asserts that a session was invalidated.
+    #[track_caller]
+    pub fn assert_invalidated(&self) {
+        let calls = self.calls();
+        assert!(
+            calls
+                .iter()
+                .any(|call| matches!(call, DriverCall::Invalidate { .. })),
+            "expected the session to be invalidated, calls were {:?}",
+            calls
+        );
+    }
+
+    fn record(&self, call: DriverCall) {
+        self.calls
+            .lock()
+            .expect("the call record is poisoned")
+            .push(call);
+    }
+}
+
+impl<D> SessionDriver for RecordingDriver<D>
+where
+    D: SessionDriver + Send,
+{
+    async fn read(&self, key: SessionKey) -> SessionResult<Option<Session>> {
+        self.record(DriverCall::Read(key.clone()));
+        self.inner.read(key).await
+    }
+
+    async fn write(&self, key: SessionKey, data: SessionData) -> SessionResult<SessionKey> {
+        self.record(DriverCall::Write(key.clone(), data.clone()));
+        self.inner.write(key, data).await
+    }
+
+    async fn destroy(&self, key: SessionKey) -> SessionResult<()> {
+        self.record(DriverCall::Destroy(key.clone()));
+        self.inner.destroy(key).await
+    }
+
+    fn ttl(&self) -> Duration {
+        self.inner.ttl()
+    }
+
+    async fn regenerate_to(
+        &self,
+        key: SessionKey,
+        new_key: SessionKey,
+        data: SessionData,
+    ) -> SessionResult<SessionKey> {
+        self.record(DriverCall::Regenerate {
+            from: key.clone(),
+            to: new_key.clone(),
+        });
+        self.inner.regenerate_to(key, new_key, data).await
+    }
+
+    async fn invalidate_to(
+        &self,
+        key: SessionKey,
+        new_key: SessionKey,
+        data: SessionData,
+    ) -> SessionResult<SessionKey> {
+        self.record(DriverCall::Invalidate {
+            from: key.clone(),
+            to: new_key.clone(),
+        });
+        self.inner.invalidate_to(key, new_key, data).await
+    }
+}
+
+/// Builds a session with a random key holding the given values.
+pub fn session_with<I, K, V>(values: I) -> Session
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<Cow<'static, str>>,
+    V: Into<Value>,
+{
+    let data: SessionData = values
+        .into_iter()
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect();
+    Session::builder(RandomKeyGenerator::default().generate())
+        .with_data(data)
+        .build()
+}
+
+/// Builds a request carrying `session` in its extensions, as if the `SessionLayer` had run.
+///
+/// This is enough to call a handler directly, without any middleware.
+pub fn request_with_session<N>(session: Session<N>) -> Request
+where
+    N: 'static,
+{
+    let mut request = Request::new(Body::empty());
+    request.extensions_mut().insert(session);
+    request
+}
+
+/// Stores `values` in the driver of `layer` and returns the key of the new session.
+///
+/// The key comes from the key generator of the layer, so the layer accepts it. Combine with
+/// [`request_with_cookie`] to send a request for a pre-seeded session through the layer.
+pub async fn seed_session<D, H, N, I, K, V>(
+    layer: &SessionLayer<D, H, N>,
+    values: I,
+) -> SessionResult<SessionKey>
+where
+    D: SessionDriver,
+    H: IntoErrorResponse,
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<Cow<'static, str>>,
+    V: Into<Value>,
+{
+    let (_, _, data) = session_with(values).into_parts();
+    let key = layer.config().generator.generate();
+    layer.driver().write(key, data).await
+}
+
+/// Builds a request sending `key` in the session cookie of `layer`.
+///
+/// The cookie is signed or encrypted when the layer reads it through a cookie jar.
+///
+/// # Panics
+/// Panics if the cookie jar of the layer refuses the session cookie.
+pub fn request_with_cookie<D, H, N>(layer: &SessionLayer<D, H, N>, key: &str) -> Request
+where
+    D: SessionDriver,
+    H: IntoErrorResponse,
+{
+    let SessionKind::Cookie(name) = layer.kind();
+    let mut headers = HeaderMap::new();
+    write_session_cookie(
+        layer.config(),
+        Cookie::new(name.clone(), key.to_owned()),
+        &mut headers,
+    )
+    .expect("the cookie jar accepts the session cookie");
+
+    let cookies: Vec<_> = set_cookies(&headers)
+        .map(|cookie| cookie.encoded().stripped().to_string())
+        .collect();
+    Request::builder()
+        .header(header::COOKIE, cookies.join("; "))
+        .body(Body::empty())
+        .expect("the request is valid")
+}
+
+/// Returns the session key set by the response in the session cookie of `layer`.
+///
+/// The cookie is verified or decrypted when the layer reads it through a cookie jar.
+pub fn session_key_from<D, H, N>(
+    layer: &SessionLayer<D, H, N>,
+    response: &Response,
+) -> Option<SessionKey>
+where
+    D: SessionDriver,
+    H: IntoErrorResponse,
+{
+    let SessionKind::Cookie(name) = layer.kind();
+    let cookie = set_cookies(response.headers()).find(|cookie| cookie.name() == name)?;
+
+    let mut headers = HeaderMap::new();
+    let value = cookie.encoded().stripped().to_string().parse().ok()?;
+    headers.insert(header::COOKIE, value);
+    read_session_cookie(layer.config(), &headers, name.clone())
+        .map(|cookie| SessionKey::from(cookie.value()))
+}
+
+fn set_cookies(headers: &HeaderMap) -> impl Iterator<Item = Cookie<'static>> + '_ {
+    headers
+        .get_all(header::SET_COOKIE)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| Cookie::parse_encoded(value.to_owned()).ok())
+}
+
+/// Sends `request` through `layer` wrapping `service`, entirely in-process.
+pub async fn round_trip<L, S>(layer: &L, service: S, request: Request) -> Response
+where
+    L: Layer<S>,
+    L::Service: Service<Request, Response = Response, Error = Infallible>,
+{
+    match layer.layer(service).oneshot(request).await {
+        Ok(response) => response,
+        Err(err) => match err {},
+    }
+}
+
+/// Asserts that the session contains `key`.
+#[track_caller]
+pub fn assert_session_has<N>(session: &Session<N>, key: &str) {
+    assert!(
+        session.has(key),
+        "expected the session to contain {:?}, data was {:?}",
+        key,
+        session.all()
+    );
+}
+
+/// Asserts that the session does not contain `key`.
+#[track_caller]
+pub fn assert_session_missing<N>(session: &Session<N>, key: &str) {
+    assert!(
+        !session.has(key),
+        "expected the session not to contain {:?}, data was {:?}",
+        key,
+        session.all()
+    );
+}
+
+/// Asserts that the session has been marked as regenerated.
+#[track_caller]
+pub fn assert_regenerated<N>(session: &Session<N>) {
+    assert_eq!(
+        session.state(),
+        SessionState::Regenerated,
+        "expected the session to be regenerated"
+    );
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use axum_core::response::IntoResponse;
+    use tower::service_fn;
+
+    use super::*;
+    use crate::{driver::MemoryDriver, ext::RequestSessionExt, middleware::SessionLayer};
+
+    async fn login(mut req: Request) -> Result<Response, Infallible> {
+        let session = req.take_session().unwrap();
+        Ok(session.insert("user_id", 1).regenerate().into_response())
+    }
+
+    #[test]
+    fn test_assertions() {
+        let session = session_with([("user_id", 1)]);
+        assert_session_has(&session, "user_id");
+        assert_session_missing(&session, "name");
+        assert_regenerated(&session.regenerate());
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let driver = RecordingDriver::new(MemoryDriver::new());
+        let layer = SessionLayer::builder().with_driver(driver.clone()).build();
+        let key = seed_session(&layer, [("name", "John")]).await.unwrap();
+        driver.clear();
+
+        let request = request_with_cookie(&layer, &key);
+        let response = round_trip(&layer, service_fn(login), request).await;
+
+        let new_key = session_key_from(&layer, &response).unwrap();
+        assert_ne!(new_key, key);
+        assert_eq!(driver.calls()[0], DriverCall::Read(key.clone()));
+        driver.assert_regenerated();
+
+        let session = driver.read(new_key).await.unwrap().unwrap();
+        assert_session_has(&session, "name");
+        assert_session_has(&session, "user_id");
+        assert!(driver.inner().read(key).await.unwrap().is_none());
+    }
+
+    #[cfg(all(feature = "signed", feature = "cortev-cookie"))]
+    #[tokio::test]
+    async fn test_round_trip_through_cookie_jar() {
+        use cortev_cookie::{CookieJar, CookieKind, EncryptionCookiePolicy};
+
+        use crate::SignedKeyGenerator;
+
+        let mut policy = EncryptionCookiePolicy::default();
+        policy.insert("__Host-id", CookieKind::Private);
+        let jar = CookieJar::builder(cookie::Key::generate())
+            .with_encryption_policy(policy)
+            .build();
+        let driver = MemoryDriver::new();
+        let layer = SessionLayer::builder()
+            .with_driver(driver.clone())
+            .with_key_generator(SignedKeyGenerator::new("secret"))
+            .with_host_cookie()
+            .with_cookie_jar(jar)
+            .with_strict_mode()
+            .build();
+        let key = seed_session(&layer, [("name", "John")]).await.unwrap();
+
+        let request = request_with_cookie(&layer, &key);
+        let cookie = request.headers()[header::COOKIE].to_str().unwrap();
+        assert!(cookie.starts_with("__Host-id="));
+        assert!(!cookie.contains(&*key));
+
+        let response = round_trip(&layer, service_fn(login), request).await;
+        let new_key = session_key_from(&layer, &response).unwrap();
+        assert_ne!(new_key, key);
+
+        let session = driver.read(new_key).await.unwrap().unwrap();
+        assert_session_has(&session, "name");
+        assert_session_has(&session, "user_id");
+    }
+}